@@ -0,0 +1,160 @@
+use super::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+/// An API key that hasn't been revoked. The key itself is only shown when it's issued;
+/// afterwards it's looked up by its digest.
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub capacity: Option<u32>,
+    pub per_minute: Option<u32>,
+    /// How many searches the key may make each day, in UTC
+    pub daily_quota: Option<i64>,
+    /// Which origins browsers may use the key from, or any if unset
+    pub allowed_origins: Option<Vec<String>>,
+}
+
+impl ApiKey {
+    /// The key's own rate limits, falling back to the configured ones
+    pub fn limits(&self) -> config::BucketLimits {
+        let default = CONFIG.rate_limits.per_key;
+        config::BucketLimits {
+            capacity: self.capacity.unwrap_or(default.capacity),
+            per_minute: self.per_minute.unwrap_or(default.per_minute),
+        }
+    }
+
+    /// Requests without an `Origin` header don't come from a browser page, so are allowed
+    pub fn allows_origin(&self, origin: Option<&str>) -> bool {
+        match (&self.allowed_origins, origin) {
+            (Some(allowed), Some(origin)) => allowed.iter().any(|allowed| allowed == origin),
+            _ => true,
+        }
+    }
+}
+
+pub fn new_api_key() -> String {
+    watch::random_token()
+}
+
+pub fn api_key_digest(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Every key that hasn't been revoked, by digest
+pub struct ApiKeys {
+    keys: HashMap<String, ApiKey>,
+}
+
+impl ApiKeys {
+    pub async fn load() -> Result<Self, UserError> {
+        let rows = PG_POOL
+            .get()
+            .await?
+            .query(
+                "SELECT id, name, digest, capacity, per_minute, daily_quota, allowed_origins \
+                 FROM api_keys WHERE revoked IS NULL",
+                &[],
+            )
+            .await?;
+
+        // Limits that don't fit fall back to the defaults
+        let limit = |row: &tokio_postgres::Row, column| {
+            row.get::<_, Option<i64>>(column)
+                .and_then(|n| u32::try_from(n).ok())
+                .filter(|n| *n > 0)
+        };
+
+        let keys = rows
+            .iter()
+            .map(|row| {
+                (
+                    row.get("digest"),
+                    ApiKey {
+                        id: row.get("id"),
+                        name: row.get("name"),
+                        capacity: limit(row, "capacity"),
+                        per_minute: limit(row, "per_minute"),
+                        daily_quota: row.get("daily_quota"),
+                        allowed_origins: row.get("allowed_origins"),
+                    },
+                )
+            })
+            .collect();
+
+        Ok(Self { keys })
+    }
+
+    pub fn find(&self, key: &str) -> Option<&ApiKey> {
+        self.keys.get(&api_key_digest(key))
+    }
+}
+
+static API_KEYS: Lazy<Reloaded<ApiKeys>> = Lazy::new(Reloaded::new);
+
+pub async fn api_keys() -> Result<Arc<ApiKeys>, UserError> {
+    API_KEYS.get(ApiKeys::load).await
+}
+
+/// Counts `searches` against a key's usage for today, unless that would exceed its quota.
+/// Returns whether they were counted.
+pub async fn count_usage(api_key: &ApiKey, searches: i64) -> Result<bool, UserError> {
+    if matches!(api_key.daily_quota, Some(quota) if searches > quota) {
+        return Ok(false);
+    }
+
+    let counted = PG_POOL
+        .get()
+        .await?
+        .query_opt(
+            "INSERT INTO api_key_usage (key_id, day, searches) VALUES ($1, $2, $3) \
+             ON CONFLICT (key_id, day) DO UPDATE \
+             SET searches = api_key_usage.searches + EXCLUDED.searches \
+             WHERE $4::bigint IS NULL OR api_key_usage.searches + EXCLUDED.searches <= $4 \
+             RETURNING searches",
+            &[
+                &api_key.id,
+                &chrono::offset::Utc::now().naive_utc().date(),
+                &searches,
+                &api_key.daily_quota,
+            ],
+        )
+        .await?;
+
+    Ok(counted.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_origin() {
+        let mut api_key = ApiKey {
+            id: 1,
+            name: "extension".to_string(),
+            capacity: None,
+            per_minute: None,
+            daily_quota: None,
+            allowed_origins: None,
+        };
+        assert!(api_key.allows_origin(None));
+        assert!(api_key.allows_origin(Some("https://example.com")));
+
+        api_key.allowed_origins = Some(vec!["moz-extension://abc".to_string()]);
+        assert!(api_key.allows_origin(None));
+        assert!(api_key.allows_origin(Some("moz-extension://abc")));
+        assert!(!api_key.allows_origin(Some("https://example.com")));
+    }
+
+    #[test]
+    fn digest() {
+        let key = new_api_key();
+        assert_eq!(api_key_digest(&key), api_key_digest(&key));
+        assert_ne!(api_key_digest(&key), api_key_digest(&new_api_key()));
+        assert_eq!(api_key_digest(&key).len(), 64);
+    }
+}
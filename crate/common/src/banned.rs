@@ -1,5 +1,8 @@
 use super::*;
+use chrono::NaiveDate;
+use serde::de::{self, Deserializer};
 use serde::Deserialize;
+use url::Url;
 
 #[allow(dead_code)]
 #[derive(Debug, Deserialize)]
@@ -8,6 +11,57 @@ pub enum Banned {
     Host(String),
     AnyScheme(String),
     Full(String),
+    /// Matches anywhere in the URL unless anchored
+    Regex(#[serde(deserialize_with = "de_regex")] Regex),
+    /// Matches a host exactly and the start of the path, which can include the query string
+    HostPath {
+        host: String,
+        path: String,
+    },
+    /// Matches every Imgur link to the image, whatever its subdomain or extension
+    ImgurId(String),
+    /// Gives a rule a reason to record, or a day it stops applying
+    Annotated {
+        rule: Box<Banned>,
+        #[serde(default)]
+        reason: Option<String>,
+        #[serde(default)]
+        expires: Option<NaiveDate>,
+    },
+}
+
+fn de_regex<'de, D>(des: D) -> Result<Regex, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Regex::new(&String::deserialize(des)?).map_err(de::Error::custom)
+}
+
+/// The IDs of the images an Imgur link points to, like `3EqtHIK` from `i.imgur.com/3EqtHIK.gifv`
+fn imgur_ids(url: &str) -> Vec<&str> {
+    if !is_link_imgur(url) {
+        return Vec::new();
+    }
+
+    let path = match url.find("://") {
+        Some(loc) => &url[loc + 3..],
+        None => url,
+    };
+    let path = path
+        .split(&['?', '#'][..])
+        .next()
+        .unwrap_or("")
+        .trim_end_matches('/');
+
+    let mut segments = path.split('/').skip(1);
+    match (segments.next(), segments.next()) {
+        (Some(ids), None) => ids
+            .split(',')
+            .map(|id| id.split('.').next().unwrap_or(""))
+            .filter(|id| !id.is_empty())
+            .collect(),
+        _ => Vec::new(),
+    }
 }
 
 impl Banned {
@@ -23,6 +77,34 @@ impl Banned {
                 .map(|loc| url.split_at(loc + 3).1 == *no_scheme)
                 .unwrap_or(false),
             Full(link) => url == *link,
+            Regex(re) => re.is_match(url),
+            HostPath { host, path } => Url::parse(url)
+                .map(|url| {
+                    let full_path = match url.query() {
+                        Some(query) => format!("{}?{}", url.path(), query),
+                        None => url.path().to_string(),
+                    };
+                    url.host_str() == Some(host.as_str()) && full_path.starts_with(path.as_str())
+                })
+                .unwrap_or(false),
+            ImgurId(id) => imgur_ids(url).contains(&id.as_str()),
+            Annotated { rule, expires, .. } => {
+                let expired = matches!(expires, Some(expires)
+                    if chrono::offset::Utc::now().naive_utc().date() >= *expires);
+
+                !expired && rule.matches(url)
+            }
+        }
+    }
+
+    /// What's recorded as the `save_error` of a post this rule bans
+    pub fn save_error(&self) -> Cow<'static, str> {
+        match self {
+            Banned::Annotated {
+                reason: Some(reason),
+                ..
+            } => format!("banned: {}", reason).into(),
+            _ => "banned".into(),
         }
     }
 }
@@ -50,4 +132,85 @@ mod tests {
     fn host_end() {
         assert!(Banned::HostEnd("sub.bad.com".to_string()).matches("https://a.sub.bad.com/asdf"));
     }
+
+    #[test]
+    fn regex() {
+        let banned =
+            Banned::Regex(Regex::new(r"^https?://i\.redd\.it/[a-z0-9]+q21\.gif$").unwrap());
+
+        assert!(banned.matches("https://i.redd.it/es10qqrtn3q21.gif"));
+        assert!(!banned.matches("https://i.redd.it/es10qqrtn3q21.jpg"));
+    }
+
+    #[test]
+    fn host_path() {
+        let banned = Banned::HostPath {
+            host: "gifsound.com".to_string(),
+            path: "/?gif=%7B%5Crtf1".to_string(),
+        };
+
+        assert!(banned.matches("http://gifsound.com/?gif=%7B%5Crtf1%5Cansi&v=2-ckIv1tiaU"));
+        assert!(!banned.matches("http://gifsound.com/?gif=i.imgur.com/IRRzso8.gif"));
+        assert!(!banned.matches("http://notgifsound.com/?gif=%7B%5Crtf1"));
+    }
+
+    #[test]
+    fn imgur_id() {
+        let banned = Banned::ImgurId("b6twNgB".to_string());
+
+        assert!(banned.matches("https://imgur.com/b6twNgB"));
+        assert!(banned.matches("https://i.imgur.com/b6twNgB.gif"));
+        assert!(banned.matches("http://i.imgur.com/b6twNgB.gifv?fb"));
+        assert!(banned.matches("https://m.imgur.com/vyyUWmX,b6twNgB"));
+        assert!(!banned.matches("https://i.imgur.com/b6twNgb.gif"));
+        assert!(!banned.matches("https://imgur.com/a/b6twNgB"));
+        assert!(!banned.matches("https://notimgur.com/b6twNgB.gif"));
+    }
+
+    #[test]
+    fn annotated() {
+        let banned = Banned::Annotated {
+            rule: Box::new(Banned::Host("bad.com".to_string())),
+            reason: Some("spam".to_string()),
+            expires: None,
+        };
+        assert!(banned.matches("https://bad.com/asdf"));
+        assert_eq!(banned.save_error(), "banned: spam");
+        assert_eq!(Banned::Host("bad.com".to_string()).save_error(), "banned");
+
+        let expired = Banned::Annotated {
+            rule: Box::new(Banned::Host("bad.com".to_string())),
+            reason: None,
+            expires: Some(NaiveDate::from_ymd_opt(2000, 1, 1).unwrap()),
+        };
+        assert!(!expired.matches("https://bad.com/asdf"));
+    }
+
+    #[test]
+    fn configured() {
+        let banned: Vec<Banned> = ron::from_str(
+            r#"[
+                ImgurId("b6twNgB"),
+                Regex("^https?://lvme\\.me/"),
+                HostPath(host: "gifsound.com", path: "/?gif=%7B"),
+                Annotated(rule: Host("bad.com"), reason: Some("spam"), expires: Some("2000-01-01")),
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(banned.len(), 4);
+        assert!(banned[1].matches("http://lvme.me/x.jpg"));
+        assert!(!banned[3].matches("https://bad.com/asdf"));
+
+        for url in &[
+            "https://i.imgur.com/TSUMR7a.gifv",
+            "http://i.redd.it/rdwk5rg0a2j21.jpg",
+        ] {
+            assert!(
+                CONFIG.banned.iter().any(|banned| banned.matches(url)),
+                "{}",
+                url
+            );
+        }
+    }
 }
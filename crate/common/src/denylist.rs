@@ -0,0 +1,178 @@
+use super::*;
+use std::sync::Arc;
+
+/// A known-bad image, and how far from it other images are denied too
+#[derive(Debug, Copy, Clone)]
+pub struct DeniedHash {
+    pub hash: Hash,
+    pub distance: u32,
+}
+
+/// A condition that leaves out images denied by the `denied_hashes` table, so queries can page
+/// past them. Like `Denylist::matches_hashes`, it checks the trimmed hash and the hash of each
+/// saved frame as well. Entries from the denylist file aren't in Postgres, so are checked
+/// afterwards with `Denylist::matches_file`.
+pub const NOT_DENIED: &str = "NOT EXISTS (SELECT FROM denied_hashes \
+                              WHERE images.hash <@ (denied_hashes.hash, denied_hashes.distance) \
+                              OR images.trimmed_hash <@ (denied_hashes.hash, denied_hashes.distance) \
+                              OR EXISTS (SELECT FROM image_frames \
+                              WHERE image_frames.image_id = images.id \
+                              AND image_frames.hash <@ (denied_hashes.hash, denied_hashes.distance)))";
+
+#[derive(Debug, Default)]
+pub struct Denylist {
+    entries: Vec<DeniedHash>,
+    /// How many of the entries, which come first, are from the denylist file
+    file_entries: usize,
+}
+
+impl Denylist {
+    /// Parses one hash per line, each optionally followed by a distance, ignoring `#` comments
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let entries = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or("").trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut fields = line.split_whitespace();
+                let hash = fields.next().unwrap().parse()?;
+                let distance = fields.next().map(str::parse).transpose()?.unwrap_or(0);
+
+                if let Some(extra) = fields.next() {
+                    return Err(format_err!(
+                        "Unexpected `{}` in denylist line: {}",
+                        extra,
+                        line
+                    ));
+                }
+
+                Ok(DeniedHash { hash, distance })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(Self {
+            file_entries: entries.len(),
+            entries,
+        })
+    }
+
+    /// Combines the configured denylist file with the `denied_hashes` table
+    pub async fn load() -> Result<Self, UserError> {
+        let mut denylist = match &CONFIG.denylist_file {
+            Some(path) => Self::parse(&tokio::fs::read_to_string(path).await?)
+                .map_err(map_ue!("invalid denylist file", Source::Internal))?,
+            None => Self::default(),
+        };
+
+        let rows = PG_POOL
+            .get()
+            .await?
+            .query("SELECT hash, distance FROM denied_hashes", &[])
+            .await?;
+
+        denylist.entries.extend(rows.iter().map(|row| DeniedHash {
+            hash: Hash(row.get::<_, i64>("hash") as u64),
+            distance: row.get::<_, i64>("distance") as u32,
+        }));
+
+        debug!("loaded {} denied hashes", denylist.entries.len());
+
+        Ok(denylist)
+    }
+
+    pub fn entries(&self) -> &[DeniedHash] {
+        &self.entries
+    }
+
+    pub fn matches(&self, hash: Hash) -> Option<&DeniedHash> {
+        Self::find(&self.entries, hash)
+    }
+
+    /// Checks the hash of the image with its borders trimmed and of each sampled frame too, so
+    /// a denied image isn't let through by adding a border or wrapping it in an animation
+    pub fn matches_hashes(&self, hashes: &Hashes) -> Option<&DeniedHash> {
+        std::iter::once(hashes.dhash)
+            .chain(hashes.trimmed)
+            .chain(hashes.frames.iter().flatten().map(|(_, hash)| *hash))
+            .find_map(|hash| self.matches(hash))
+    }
+
+    /// Only checks the entries from the denylist file, for results already filtered by
+    /// `NOT_DENIED`
+    pub fn matches_file(&self, hash: Hash) -> Option<&DeniedHash> {
+        Self::find(&self.entries[..self.file_entries], hash)
+    }
+
+    fn find(entries: &[DeniedHash], hash: Hash) -> Option<&DeniedHash> {
+        entries
+            .iter()
+            .find(|denied| distance(hash, denied.hash) <= denied.distance)
+    }
+}
+
+static DENYLIST: Lazy<Reloaded<Denylist>> = Lazy::new(Reloaded::new);
+
+pub async fn denylist() -> Result<Arc<Denylist>, UserError> {
+    DENYLIST.get(Denylist::load).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let denylist = Denylist::parse(
+            "# Known-bad images\n\
+             \n\
+             0xb4e25a3c0f0f1e2d 3\n\
+             6149003018664301909 # exact only\n",
+        )
+        .unwrap();
+
+        assert_eq!(denylist.entries().len(), 2);
+        assert_eq!(denylist.entries()[0].distance, 3);
+        assert_eq!(denylist.entries()[1].hash.0, 6149003018664301909);
+        assert_eq!(denylist.entries()[1].distance, 0);
+
+        assert!(Denylist::parse("0xb4e25a3c0f0f1e2d 3 4").is_err());
+        assert!(Denylist::parse("not-a-hash").is_err());
+    }
+
+    #[test]
+    fn matches() {
+        let denylist = Denylist::parse("0xff00 2").unwrap();
+
+        assert!(denylist.matches(Hash(0xff00)).is_some());
+        assert!(denylist.matches(Hash(0xff03)).is_some());
+        assert!(denylist.matches(Hash(0xff07)).is_none());
+    }
+
+    #[test]
+    fn matches_hashes() {
+        let denylist = Denylist::parse("0xff00 2").unwrap();
+
+        let mut hashes = Hashes::new(Hash(0x1));
+        assert!(denylist.matches_hashes(&hashes).is_none());
+
+        hashes.frames = Some(vec![(0, Hash(0x2)), (3, Hash(0xff01))]);
+        assert!(denylist.matches_hashes(&hashes).is_some());
+
+        hashes.frames = None;
+        hashes.trimmed = Some(Hash(0xff03));
+        assert!(denylist.matches_hashes(&hashes).is_some());
+    }
+
+    #[test]
+    fn matches_file() {
+        let mut denylist = Denylist::parse("0xff00 2").unwrap();
+        denylist.entries.push(DeniedHash {
+            hash: Hash(0x1),
+            distance: 0,
+        });
+
+        assert!(denylist.matches(Hash(0x1)).is_some());
+        assert!(denylist.matches_file(Hash(0x1)).is_none());
+        assert!(denylist.matches_file(Hash(0xff03)).is_some());
+    }
+}
@@ -1,9 +1,15 @@
 use super::*;
 
+use hyper::client::connect::dns::Name;
+use ipnet::IpNet;
 use percent_encoding::{percent_decode, utf8_percent_encode, AsciiSet, CONTROLS};
-use reqwest::StatusCode;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::{redirect, StatusCode};
 use serde_json::Value;
-use url::Url;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio_postgres::types::ToSql;
+use url::{Host, Url};
 
 const FRAGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
 
@@ -41,12 +47,27 @@ pub fn is_link_gifsound(link: &str) -> bool {
     GIFSOUND_LINK_RE.is_match(link)
 }
 
-static WIKIPEDIA_FILE_RE: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r"(?i)^(?:[^.]+\.)?(?:wikipedia|wiktionary|wikiquote|wikibooks|wikisource|wikinews|wikiversity|wikispecies|mediawiki|wikidata|wikivoyage|wikimedia).org(?-i)/wiki/((?i:Image|File):[^#?]+)").unwrap()
+static WIKIPEDIA_HOST_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)^(?:[^.]+\.)?(?:wikipedia|wiktionary|wikiquote|wikibooks|wikisource|wikinews|wikiversity|wikispecies|mediawiki|wikidata|wikivoyage|wikimedia)\.org$").unwrap()
 });
 
+static WIKIPEDIA_FILE_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^/wiki/((?i:Image|File):[^#?]+)").unwrap());
+
+/// The title of the file a Wikimedia link is to. The host is matched on its own, so a path
+/// that merely looks like a Wikimedia host doesn't count.
+fn wikipedia_file_title(url: &Url) -> Option<&str> {
+    if !WIKIPEDIA_HOST_RE.is_match(url.host_str()?) {
+        return None;
+    }
+
+    Some(WIKIPEDIA_FILE_RE.captures(url.path())?.get(1)?.as_str())
+}
+
 pub fn is_wikipedia_file(link: &str) -> bool {
-    WIKIPEDIA_FILE_RE.is_match(link)
+    Url::parse(link)
+        .map(|url| wikipedia_file_title(&url).is_some())
+        .unwrap_or(false)
 }
 
 pub fn is_link_special(link: &str) -> bool {
@@ -56,17 +77,18 @@ pub fn is_link_special(link: &str) -> bool {
         || is_wikipedia_file(link)
 }
 
-pub async fn follow_link(url: Url) -> Result<String, UserError> {
+/// Finds the image a link leads to, making any API requests with `policy`'s client
+pub async fn follow_link(url: Url, policy: FetchPolicy) -> Result<String, UserError> {
     let link = if is_link_imgur(url.as_str()) {
-        follow_imgur(url).await?
-    } else if is_wikipedia_file(url.as_str()) {
-        follow_wikipedia(url).await?
+        follow_imgur(url, policy).await?
+    } else if wikipedia_file_title(&url).is_some() {
+        follow_wikipedia(url, policy).await?
     } else if is_link_gifsound(url.as_str()) {
         follow_gifsound(url)?
     } else if EXT_RE.is_match(url.as_str()) {
         url.into()
     } else if is_link_gfycat(url.as_str()) {
-        follow_gfycat(url).await?
+        follow_gfycat(url, policy).await?
     } else {
         url.into()
     };
@@ -108,7 +130,7 @@ fn follow_gifsound(url: Url) -> Result<String, UserError> {
     ))
 }
 
-async fn follow_gfycat(url: Url) -> Result<String, UserError> {
+async fn follow_gfycat(url: Url, policy: FetchPolicy) -> Result<String, UserError> {
     static GFY_ID_SEL: Lazy<Regex> = Lazy::new(|| Regex::new(r"^/([[:alpha:]]+)").unwrap());
 
     #[derive(Deserialize)]
@@ -123,7 +145,8 @@ async fn follow_gfycat(url: Url) -> Result<String, UserError> {
         gfy_item: GfyItem,
     }
 
-    let resp = REQW_CLIENT
+    let resp = policy
+        .client()
         .get(&format!(
             "https://api.gfycat.com/v1/gfycats/{}",
             GFY_ID_SEL
@@ -153,34 +176,31 @@ async fn follow_gfycat(url: Url) -> Result<String, UserError> {
         .mobile_poster_url)
 }
 
-async fn make_imgur_api_request(api_link: String) -> Result<Value, UserError> {
-    static API_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
-        reqwest::Client::builder()
-            .timeout(Duration::from_secs(60))
-            .default_headers({
-                let mut headers = COMMON_HEADERS.clone();
-                headers.insert(
-                    "X-RapidAPI-Host",
-                    HeaderValue::from_static("imgur-apiv3.p.rapidapi.com"),
-                );
-                headers.insert(
-                    "X-RapidAPI-Key",
-                    HeaderValue::from_static(&SECRETS.imgur.rapidapi_key),
-                );
-                headers.insert(
-                    header::AUTHORIZATION,
-                    format!("Client-ID {}", SECRETS.imgur.client_id)
-                        .parse()
-                        .unwrap(),
-                );
-                headers
-            })
-            .build()
-            .unwrap()
+async fn make_imgur_api_request(api_link: String, policy: FetchPolicy) -> Result<Value, UserError> {
+    static API_HEADERS: Lazy<HeaderMap<HeaderValue>> = Lazy::new(|| {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-RapidAPI-Host",
+            HeaderValue::from_static("imgur-apiv3.p.rapidapi.com"),
+        );
+        headers.insert(
+            "X-RapidAPI-Key",
+            HeaderValue::from_static(&SECRETS.imgur.rapidapi_key),
+        );
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Client-ID {}", SECRETS.imgur.client_id)
+                .parse()
+                .unwrap(),
+        );
+        headers
     });
 
-    let resp = API_CLIENT
+    let resp = policy
+        .client()
         .get(&api_link)
+        .headers(API_HEADERS.clone())
+        .timeout(Duration::from_secs(60))
         .send()
         .map_err(map_ue!("couldn't reach Imgur API"))
         .await?;
@@ -254,7 +274,7 @@ where
         .ok_or(ue_save!("couldn't find Imgur ID in URL", "imgur_no_id"))
 }
 
-async fn follow_imgur(mut url: Url) -> Result<String, UserError> {
+async fn follow_imgur(mut url: Url, policy: FetchPolicy) -> Result<String, UserError> {
     static GIFV_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\.(?:gifv|webm|mp4)($|[?#])").unwrap());
     static EXT_RE: Lazy<Regex> =
         Lazy::new(|| Regex::new(r"(?i)[[:alnum:]]\.(?:jpg|png)[[:alnum:]]+").unwrap());
@@ -296,7 +316,7 @@ async fn follow_imgur(mut url: Url) -> Result<String, UserError> {
         }
         let id = id_segment(&segments, 1)?;
         let api_link = format!("https://imgur-apiv3.p.rapidapi.com/3/album/{}/images", id);
-        let json = make_imgur_api_request(api_link).await?;
+        let json = make_imgur_api_request(api_link, policy).await?;
         Ok(GIFV_RE
             .replace(
                 json["data"]
@@ -320,7 +340,7 @@ async fn follow_imgur(mut url: Url) -> Result<String, UserError> {
         }
         let id = id_segment(&segments, 1)?;
         let api_link = format!("https://imgur-apiv3.p.rapidapi.com/3/gallery/album/{}", id);
-        let json = make_imgur_api_request(api_link).await?;
+        let json = make_imgur_api_request(api_link, policy).await?;
         Ok(GIFV_RE
             .replace(
                 json["data"]["images"]
@@ -341,7 +361,7 @@ async fn follow_imgur(mut url: Url) -> Result<String, UserError> {
     }
 }
 
-async fn follow_wikipedia(url: Url) -> Result<String, UserError> {
+async fn follow_wikipedia(url: Url, policy: FetchPolicy) -> Result<String, UserError> {
     #[derive(Debug, Deserialize)]
     struct ImageInfo {
         mime: String,
@@ -361,11 +381,7 @@ async fn follow_wikipedia(url: Url) -> Result<String, UserError> {
         query: Query,
     }
 
-    let title = WIKIPEDIA_FILE_RE
-        .captures(url.as_str())
-        .and_then(|c| c.get(1))
-        .map(|m| m.as_str())
-        .ok_or(ue!("couldn't extract title"))?;
+    let title = wikipedia_file_title(&url).ok_or(ue!("couldn't extract title"))?;
 
     let title = percent_decode(title.as_bytes())
         .decode_utf8()
@@ -387,7 +403,8 @@ async fn follow_wikipedia(url: Url) -> Result<String, UserError> {
     )
     .map_err(map_ue!("couldn't create Wikipedia API URL", Source::User))?;
 
-    let resp = REQW_CLIENT
+    let resp = policy
+        .client()
         .get(api_url.as_str())
         .send()
         .map_err(map_ue!("couldn't reach Wikipedia API"))
@@ -429,18 +446,238 @@ pub fn host_ends_with(url: &str, end: &str) -> bool {
     get_host(url).map(|h| h.ends_with(end)).unwrap_or(false)
 }
 
+/// Which hosts a link's image may be fetched from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FetchPolicy {
+    /// Only hosts with public addresses, for links anyone can submit to the site
+    Public,
+    /// Any host, for ingesters whose input is trusted
+    Trusted,
+}
+
+/// How many redirects a fetch under the public policy follows before giving up
+const MAX_REDIRECTS: usize = 5;
+
+/// Networks that are private, reserved or otherwise not on the public internet
+static RESERVED_NETS: Lazy<Vec<IpNet>> = Lazy::new(|| {
+    [
+        "0.0.0.0/8",
+        "10.0.0.0/8",
+        "100.64.0.0/10",
+        "127.0.0.0/8",
+        "169.254.0.0/16",
+        "172.16.0.0/12",
+        "192.0.0.0/24",
+        "192.0.2.0/24",
+        "192.88.99.0/24",
+        "192.168.0.0/16",
+        "198.18.0.0/15",
+        "198.51.100.0/24",
+        "203.0.113.0/24",
+        "224.0.0.0/4",
+        "240.0.0.0/4",
+        "::/96",
+        "64:ff9b:1::/48",
+        "100::/64",
+        "2001::/23",
+        "2001:db8::/32",
+        "fc00::/7",
+        "fe80::/10",
+        "fec0::/10",
+        "ff00::/8",
+    ]
+    .iter()
+    .map(|net| net.parse().unwrap())
+    .collect()
+});
+
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    // IPv6 addresses that embed an IPv4 address are only as public as it is
+    let ip = match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, ..] | [0x64, 0xff9b, 0, 0, 0, 0, ..] => {
+                let [.., a, b, c, d] = v6.octets();
+                IpAddr::from([a, b, c, d])
+            }
+            [0x2002, high, low, ..] => {
+                IpAddr::from([(high >> 8) as u8, high as u8, (low >> 8) as u8, low as u8])
+            }
+            _ => ip,
+        },
+        IpAddr::V4(_) => ip,
+    };
+
+    !RESERVED_NETS.iter().any(|net| net.contains(&ip))
+}
+
+/// Why a fetch under the public policy was refused
+#[derive(Debug)]
+struct PrivateHost;
+
+impl fmt::Display for PrivateHost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("host has no public addresses")
+    }
+}
+
+impl std::error::Error for PrivateHost {}
+
+/// Whether a URL's host is public, as far as can be told without resolving it;
+/// domains are checked by `PublicResolver` when they're connected to
+fn is_public_literal(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Ipv4(ip)) => is_public_ip(ip.into()),
+        Some(Host::Ipv6(ip)) => is_public_ip(ip.into()),
+        Some(Host::Domain(_)) => true,
+        None => false,
+    }
+}
+
+/// Whether a URL's host only has public addresses, for URLs that are stored to be fetched later.
+/// They're still fetched under the public policy, since what a host resolves to can change.
+pub async fn resolves_publicly(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(domain)) => match tokio::net::lookup_host((domain, 0)).await {
+            Ok(addrs) => {
+                let addrs = addrs.collect::<Vec<SocketAddr>>();
+                !addrs.is_empty() && addrs.iter().all(|addr| is_public_ip(addr.ip()))
+            }
+            Err(_) => false,
+        },
+        _ => is_public_literal(url),
+    }
+}
+
+/// Resolves hosts like the system does, but only to their public addresses
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect::<Vec<SocketAddr>>();
+
+            if addrs.is_empty() {
+                Err(PrivateHost.into())
+            } else {
+                Ok(Box::new(addrs.into_iter()) as Addrs)
+            }
+        })
+    }
+}
+
+/// Checks hosts when they're resolved rather than beforehand, so that every address
+/// connected to is public, including after redirects
+static PUBLIC_REQW_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .default_headers(COMMON_HEADERS.clone())
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() > MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if !is_public_literal(attempt.url()) {
+                attempt.error(PrivateHost)
+            } else {
+                attempt.follow()
+            }
+        }))
+        // A proxy would resolve hosts itself
+        .no_proxy()
+        .build()
+        .unwrap()
+});
+
+impl FetchPolicy {
+    /// The client for requests to URLs chosen under this policy
+    pub fn client(self) -> &'static reqwest::Client {
+        match self {
+            FetchPolicy::Public => &PUBLIC_REQW_CLIENT,
+            FetchPolicy::Trusted => &REQW_CLIENT,
+        }
+    }
+}
+
+fn is_private_host_error(error: &reqwest::Error) -> bool {
+    let mut source = std::error::Error::source(error);
+    while let Some(error) = source {
+        if error.is::<PrivateHost>() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
 pub enum GetKind {
     Cache(HashDest, i64),
     Request(HeaderMap),
 }
 
 pub struct HashGotten {
-    pub hash: Hash,
+    pub hashes: Hashes,
     pub end_link: String,
     pub get_kind: GetKind,
 }
 
-pub async fn get_hash(orig_link: &str) -> Result<HashGotten, UserError> {
+/// Hashes an image, or finds its hashes if it's been hashed before. Its frames are only sampled
+/// if `frames`.
+pub async fn get_hash(
+    orig_link: &str,
+    policy: FetchPolicy,
+    frames: bool,
+) -> Result<HashGotten, UserError> {
+    let gotten = fetch_hash(orig_link, policy, true, false, frames).await?;
+
+    metrics::HASH_GETS
+        .with_label_values(&[match gotten.get_kind {
+            GetKind::Cache(..) => "cache",
+            GetKind::Request(_) => "request",
+        }])
+        .inc();
+
+    Ok(gotten)
+}
+
+/// Downloads and hashes an image even if it's been hashed before, for the hashes that aren't
+/// stored, like those of its flips and rotations if `transforms` or of its frames if `frames`.
+/// Nothing is saved.
+pub async fn refetch_hash(
+    link: &str,
+    policy: FetchPolicy,
+    transforms: bool,
+    frames: bool,
+) -> Result<Hashes, UserError> {
+    Ok(fetch_hash(link, policy, false, transforms, frames)
+        .await?
+        .hashes)
+}
+
+/// An image's hashes if it's been hashed before. The cache only has the frames of images that
+/// were searched by frame, so isn't used when they're wanted.
+async fn find_existing(
+    link: &str,
+    use_cache: bool,
+    frames: bool,
+) -> Result<Option<(Hashes, HashDest, i64)>, UserError> {
+    if !use_cache {
+        return Ok(None);
+    }
+
+    Ok(get_existing(link)
+        .await?
+        .filter(|(_, hash_dest, _)| !frames || *hash_dest == HashDest::Images))
+}
+
+async fn fetch_hash(
+    orig_link: &str,
+    policy: FetchPolicy,
+    use_cache: bool,
+    transforms: bool,
+    frames: bool,
+) -> Result<HashGotten, UserError> {
     static EXT_REPLACE_RE: Lazy<Regex> =
         Lazy::new(|| Regex::new(r"^(.+?)\.[[:alnum:]]+$").unwrap());
 
@@ -464,19 +701,29 @@ pub async fn get_hash(orig_link: &str) -> Result<HashGotten, UserError> {
             .map(|h| h.ends_with("i.pximg.net"))
             .unwrap_or(false);
 
-    let mut link = follow_link(url).await?;
+    let mut link = follow_link(url, policy).await?;
 
-    let found = get_existing(&link).await?;
+    if policy == FetchPolicy::Public
+        && !matches!(Url::parse(&link), Ok(url) if is_public_literal(&url))
+    {
+        return Err(ue!(
+            "links to private addresses aren't allowed",
+            Source::User
+        ));
+    }
+
+    let found = find_existing(&link, use_cache, frames).await?;
 
-    if let Some((hash, hash_dest, id)) = found {
+    if let Some((hashes, hash_dest, id)) = found {
         return Ok(HashGotten {
-            hash,
+            hashes,
             end_link: link,
             get_kind: GetKind::Cache(hash_dest, id),
         });
     }
 
-    let resp = REQW_CLIENT
+    let resp = policy
+        .client()
         .get(&link)
         .header(header::ACCEPT, {
             if is_photobucket {
@@ -496,7 +743,15 @@ pub async fn get_hash(orig_link: &str) -> Result<HashGotten, UserError> {
 
     let resp = resp
         .send()
-        .map_err(map_ue!("couldn't connect to image host"))
+        .map_err(|e| {
+            if is_private_host_error(&e) {
+                ue!("links to private addresses aren't allowed", Source::User)
+            } else if e.is_redirect() {
+                map_ue!("too many redirects")(e)
+            } else {
+                map_ue!("couldn't connect to image host")(e)
+            }
+        })
         .await?
         .error_for_status()
         .map_err(error_for_status_ue)?;
@@ -535,11 +790,11 @@ pub async fn get_hash(orig_link: &str) -> Result<HashGotten, UserError> {
                 .to_owned()
                 .to_string();
 
-            let found = get_existing(&link).await?;
+            let found = find_existing(&link, use_cache, frames).await?;
 
-            if let Some((hash, hash_dest, id)) = found {
+            if let Some((hashes, hash_dest, id)) = found {
                 return Ok(HashGotten {
-                    hash,
+                    hashes,
                     end_link: link,
                     get_kind: GetKind::Cache(hash_dest, id),
                 });
@@ -549,55 +804,69 @@ pub async fn get_hash(orig_link: &str) -> Result<HashGotten, UserError> {
 
     let headers = resp.headers().to_owned();
 
-    let image = &resp
+    let image = resp
         .bytes()
         .map_err(map_ue_save!("couldn't download image", "download_image"))
         .await?;
 
-    let hash = std::panic::catch_unwind(|| hash_from_memory(image))
-        .map_err(|_e| ue_save!("image panicked!", "image_panic", Source::User))??;
+    let hashes = spawn_hashes_from_memory(image, transforms, frames).await?;
 
     Ok(HashGotten {
-        hash,
+        hashes,
         end_link: link,
         get_kind: GetKind::Request(headers),
     })
 }
 
 pub struct HashSaved {
-    pub hash: Hash,
+    pub hashes: Hashes,
     pub hash_dest: HashDest,
     pub id: i64,
+    /// Whether this created a new row in `images`
+    pub stored: bool,
 }
 
 async fn poss_move_row(
-    hash: Hash,
+    hashes: Hashes,
     hash_dest: HashDest,
     found_hash_dest: HashDest,
     id: i64,
 ) -> Result<HashSaved, UserError> {
     if hash_dest == found_hash_dest || hash_dest == HashDest::ImageCache {
         Ok(HashSaved {
-            hash,
+            hashes,
             hash_dest,
             id,
+            stored: false,
         })
     } else {
         let mut client = PG_POOL.get().await?;
         let trans = client.transaction().await?;
         let stmt = trans
             .prepare(
-                "INSERT INTO images \
-                 (link, hash, no_store, no_cache, expires, etag, \
-                 must_revalidate, retrieved_on) \
-                 SELECT link, hash, no_store, no_cache, expires, etag, \
-                 must_revalidate, retrieved_on FROM image_cache WHERE id = $1 \
-                 RETURNING id",
+                format!(
+                    "INSERT INTO images \
+                     (link, {0}, no_store, no_cache, expires, etag, \
+                     must_revalidate, retrieved_on) \
+                     SELECT link, {0}, no_store, no_cache, expires, etag, \
+                     must_revalidate, retrieved_on FROM image_cache WHERE id = $1 \
+                     RETURNING id",
+                    Hashes::columns()
+                )
+                .as_str(),
             )
             .await?;
 
         let new_id = trans.query_one(&stmt, &[&id]).await?.get::<_, i64>("id");
 
+        trans
+            .execute(
+                "INSERT INTO image_frames (image_id, frame, hash) \
+                 SELECT $2, frame, hash FROM image_cache_frames WHERE image_id = $1",
+                &[&id, &new_id],
+            )
+            .await?;
+
         let stmt = trans
             .prepare("DELETE FROM image_cache WHERE id = $1")
             .await?;
@@ -606,22 +875,32 @@ async fn poss_move_row(
         trans.commit().await?;
 
         Ok(HashSaved {
-            hash,
+            hashes,
             hash_dest: HashDest::Images,
             id: new_id,
+            stored: true,
         })
     }
 }
 
-pub async fn save_hash(link: &str, hash_dest: HashDest) -> Result<HashSaved, UserError> {
+pub async fn save_hash(
+    link: &str,
+    hash_dest: HashDest,
+    policy: FetchPolicy,
+) -> Result<HashSaved, UserError> {
     let HashGotten {
-        hash,
+        hashes,
         end_link: link,
         get_kind,
-    } = get_hash(link).await?;
+    } = get_hash(link, policy, hash_dest == HashDest::Images).await?;
+
+    if denylist().await?.matches_hashes(&hashes).is_some() {
+        return Err(ue_save!("image is denied", "hash_denied"));
+    }
+
     match get_kind {
         GetKind::Cache(found_hash_dest, id) => {
-            poss_move_row(hash, hash_dest, found_hash_dest, id).await
+            poss_move_row(hashes, hash_dest, found_hash_dest, id).await
         }
         GetKind::Request(headers) => {
             let now = chrono::offset::Utc::now().naive_utc();
@@ -631,60 +910,101 @@ pub async fn save_hash(link: &str, hash_dest: HashDest) -> Result<HashSaved, Use
                 .and_then(|s| cache_control::with_str(s).ok());
             let cc = cc.as_ref();
 
+            let no_store = cc.map(|cc| cc.no_store);
+            let no_cache = cc.map(|cc| cc.no_cache);
+            // max-age is in seconds from now, and too large a one is left to Expires
+            let expires = cc
+                .and_then(|cc| cc.max_age)
+                .and_then(|n| chrono::Duration::from_std(Duration::from_secs(n)).ok())
+                .and_then(|max_age| now.checked_add_signed(max_age))
+                .or_else(|| {
+                    headers
+                        .get(header::EXPIRES)
+                        .and_then(|hv| hv.to_str().ok())
+                        .and_then(|s| DateTime::parse_from_rfc2822(s).ok())
+                        .map(|dt| dt.naive_utc())
+                });
+            let etag = headers.get(header::ETAG).and_then(|hv| hv.to_str().ok());
+            let must_revalidate = cc.map(|cc| cc.must_revalidate);
+            let hash_values = hashes.values();
+
+            let mut args: Vec<&(dyn ToSql + Sync)> = vec![
+                &link,
+                &no_store,
+                &no_cache,
+                &expires,
+                &etag,
+                &must_revalidate,
+                &now,
+            ];
+            let hash_placeholders = hash_values
+                .iter()
+                .map(|hash| {
+                    args.push(hash.as_ref());
+                    format!("${}", args.len())
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+
             let mut client = PG_POOL.get().await?;
             let trans = client.transaction().await?;
             let stmt = trans
                 .prepare(
                     format!(
-                        "INSERT INTO {} (link, hash, no_store, no_cache, expires, \
-                         etag, must_revalidate, retrieved_on) \
-                         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+                        "INSERT INTO {} (link, no_store, no_cache, expires, \
+                         etag, must_revalidate, retrieved_on, {}) \
+                         VALUES ($1, $2, $3, $4, $5, $6, $7, {}) \
                          ON CONFLICT DO NOTHING \
                          RETURNING id",
-                        hash_dest.table_name()
+                        hash_dest.table_name(),
+                        Hashes::columns(),
+                        hash_placeholders
                     )
                     .as_str(),
                 )
                 .await?;
 
-            let rows = trans
-                .query(
-                    &stmt,
-                    &[
-                        &link,
-                        &hash,
-                        &cc.map(|cc| cc.no_store),
-                        &cc.map(|cc| cc.no_cache),
-                        &cc.and_then(|cc| cc.max_age)
-                            .map(|n| NaiveDateTime::from_timestamp(n as i64, 0))
-                            .or_else(|| {
-                                headers
-                                    .get(header::EXPIRES)
-                                    .and_then(|hv| hv.to_str().ok())
-                                    .and_then(|s| DateTime::parse_from_rfc2822(s).ok())
-                                    .map(|dt| dt.naive_utc())
-                            }),
-                        &headers.get(header::ETAG).and_then(|hv| hv.to_str().ok()),
-                        &cc.map(|cc| cc.must_revalidate),
-                        &now,
-                    ],
-                )
-                .await?;
+            let rows = trans.query(&stmt, &args).await?;
+
+            // An ingested image may already be in the cache, which was passed over since it
+            // might not have the image's frames, so that copy is dropped
+            if hash_dest == HashDest::Images {
+                trans
+                    .execute("DELETE FROM image_cache WHERE link = $1", &[&link])
+                    .await?;
+            }
+
+            if let Some(row) = rows.first() {
+                let id: i64 = row.get("id");
+                let stmt = trans
+                    .prepare(
+                        format!(
+                            "INSERT INTO {} (image_id, frame, hash) VALUES ($1, $2, $3)",
+                            hash_dest.frames_table_name()
+                        )
+                        .as_str(),
+                    )
+                    .await?;
+                for (frame, hash) in hashes.frames.iter().flatten() {
+                    trans.execute(&stmt, &[&id, &(*frame as i32), hash]).await?;
+                }
+            }
 
             trans.commit().await?;
 
             // Postgres will return no rows on a conflict, and a row with the new id on success
             match rows.first() {
                 Some(row) => Ok(HashSaved {
-                    hash,
+                    hashes,
                     hash_dest,
                     id: row.get("id"),
+                    stored: hash_dest == HashDest::Images,
                 }),
                 None => {
                     let found = get_existing(&link).await?;
                     match found {
-                        Some((hash, found_hash_dest, id)) => {
-                            poss_move_row(hash, hash_dest, found_hash_dest, id).await
+                        Some((hashes, found_hash_dest, id)) => {
+                            poss_move_row(hashes, hash_dest, found_hash_dest, id).await
                         }
                         None => Err(ue!("conflict but no existing match")),
                     }
@@ -701,20 +1021,53 @@ mod tests {
     #[tokio::test]
     async fn follow_async() {
         assert_eq!(
-            follow_imgur(Url::parse("http://www.i.imgur.com/3EqtHIK.jpg").unwrap())
-                .await
-                .unwrap(),
+            follow_imgur(
+                Url::parse("http://www.i.imgur.com/3EqtHIK.jpg").unwrap(),
+                FetchPolicy::Trusted
+            )
+            .await
+            .unwrap(),
             "https://i.imgur.com/3EqtHIK.jpg"
         );
 
         assert_eq!(
-            follow_imgur(Url::parse("http://imgur.com/vyyUWmX,m8YtXvI,Fay1RGQ,DKFJDkI").unwrap())
-                .await
-                .unwrap(),
+            follow_imgur(
+                Url::parse("http://imgur.com/vyyUWmX,m8YtXvI,Fay1RGQ,DKFJDkI").unwrap(),
+                FetchPolicy::Trusted
+            )
+            .await
+            .unwrap(),
             "https://i.imgur.com/vyyUWmX.jpg"
         );
     }
 
+    #[test]
+    fn public_ip() {
+        for ip in &["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+
+        for ip in &[
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.31.255.255",
+            "192.168.0.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "2002:c0a8:0101::1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
     #[test]
     fn follow_sync() {
         assert_eq!(
@@ -734,6 +1087,12 @@ mod tests {
         assert!(!is_wikipedia_file(
             "http://en.www.wikipedia.org/wiki/File:Virtual-Boy-Set.png"
         ));
+        assert!(!is_wikipedia_file(
+            "http://localhost/a.wikipedia.org/wiki/File:x.png"
+        ));
+        assert!(!is_wikipedia_file(
+            "http://wikipedia.org.internal/wiki/File:x.png"
+        ));
     }
 
     #[test]
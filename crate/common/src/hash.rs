@@ -1,8 +1,16 @@
 use super::{map_ue_save, ue_save, Source, UserError};
 use bytes::BytesMut;
-use image::{imageops, load_from_memory, DynamicImage, GrayImage};
+use image::codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder};
+use image::{
+    guess_format, imageops, load_from_memory, AnimationDecoder, DynamicImage, Frames, GrayImage,
+    ImageFormat,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryInto;
 use std::fmt::{self, Display, Formatter};
-use tokio_postgres::types;
+use std::io::Cursor;
+use std::str::FromStr;
+use tokio_postgres::{types, Row};
 
 #[derive(Debug, Copy, Clone)]
 pub struct Hash(pub u64);
@@ -13,6 +21,41 @@ impl Display for Hash {
     }
 }
 
+impl FromStr for Hash {
+    type Err = failure::Error;
+
+    /// Parses a hash written in hexadecimal (`0x`-prefixed), decimal (signed, as Postgres stores
+    /// it, or unsigned), or big-endian base64, in that order. Hex needs its prefix so digits
+    /// alone are never ambiguous, and base64 that's only digits needs its `=` padding.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            return Ok(Hash(u64::from_str_radix(hex, 16)?));
+        }
+
+        let unsigned = s.strip_prefix('-').unwrap_or(s);
+        if !unsigned.is_empty() && unsigned.bytes().all(|b| b.is_ascii_digit()) {
+            return Ok(Hash(if unsigned.len() == s.len() {
+                s.parse::<u64>()?
+            } else {
+                s.parse::<i64>()? as u64
+            }));
+        }
+
+        let bytes = base64::decode_config(s.trim_end_matches('='), base64::STANDARD_NO_PAD)
+            .or_else(|_| base64::decode_config(s.trim_end_matches('='), base64::URL_SAFE_NO_PAD))
+            .map_err(|_| failure::format_err!("Invalid hash: {}", s))?;
+
+        let bytes: [u8; 8] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| failure::format_err!("Hash isn't 64 bits: {}", s))?;
+
+        Ok(Hash(u64::from_be_bytes(bytes)))
+    }
+}
+
 impl types::ToSql for Hash {
     fn to_sql(
         &self,
@@ -29,40 +72,774 @@ impl types::ToSql for Hash {
     types::to_sql_checked!();
 }
 
-pub fn dhash(img: DynamicImage) -> Result<Hash, UserError> {
-    let small_img = imageops::thumbnail(&grayscale(&img)?, 9, 8);
+/// A dHash of a 17x16 thumbnail, stored as `bit(256)`. Too wide for the index, but finer than
+/// `Hash` at telling apart the many images within a few bits of each other.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WideHash(pub [u8; 32]);
+
+/// How many bits of a `WideHash` correspond to one of a `Hash`
+pub const WIDE_SCALE: i64 = 4;
+
+impl WideHash {
+    pub fn of(gray: &GrayImage) -> Self {
+        let small_img = imageops::thumbnail(gray, 17, 16);
+
+        let mut bytes = [0; 32];
+        for y in 0..16 {
+            for x in 0..16 {
+                if small_img.get_pixel(x, y)[0] > small_img.get_pixel(x + 1, y)[0] {
+                    let i = (x + y * 16) as usize;
+                    bytes[i / 8] |= 0x80 >> (i % 8);
+                }
+            }
+        }
+
+        WideHash(bytes)
+    }
+
+    pub fn distance(&self, other: &WideHash) -> u32 {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+}
+
+/// 64 hexadecimal digits
+impl Display for WideHash {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl FromStr for WideHash {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s.trim())?;
+        Ok(WideHash(bytes.as_slice().try_into().map_err(|_| {
+            failure::format_err!("Wide hash isn't 256 bits: {}", s)
+        })?))
+    }
+}
+
+impl types::ToSql for WideHash {
+    fn to_sql(
+        &self,
+        _t: &types::Type,
+        w: &mut BytesMut,
+    ) -> Result<types::IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        // Bit strings are sent as their length in bits, then the bits packed high first
+        w.extend_from_slice(&256i32.to_be_bytes());
+        w.extend_from_slice(&self.0);
+        Ok(types::IsNull::No)
+    }
+
+    fn accepts(t: &types::Type) -> bool {
+        matches!(*t, types::Type::BIT | types::Type::VARBIT)
+    }
+
+    types::to_sql_checked!();
+}
+
+impl<'a> types::FromSql<'a> for WideHash {
+    fn from_sql(
+        _t: &types::Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        match raw.split_at(4.min(raw.len())) {
+            (len, bytes) if len == 256i32.to_be_bytes() => Ok(WideHash(bytes.try_into()?)),
+            _ => Err("wide hash isn't 256 bits".into()),
+        }
+    }
+
+    fn accepts(t: &types::Type) -> bool {
+        matches!(*t, types::Type::BIT | types::Type::VARBIT)
+    }
+}
+
+/// How far a pixel's brightness may be from a border's and still be part of it
+const BORDER_TOLERANCE: u8 = 24;
+
+/// How much of a row or column must match the border for it to be part of the border. Less
+/// than all of it, so caption bars and watermark strips with text on them are trimmed too.
+const BORDER_FRACTION: f64 = 0.75;
+
+/// How many of `lines`, from the outside in, are part of a border the color of the first
+fn border_len(lines: impl Iterator<Item = Vec<u8>>) -> u32 {
+    let mut color = None;
+    let mut len = 0;
+
+    for mut line in lines {
+        let color = *color.get_or_insert_with(|| {
+            line.sort_unstable();
+            line[line.len() / 2]
+        });
+
+        let matching = line
+            .iter()
+            .filter(|pixel| pixel.abs_diff(color) <= BORDER_TOLERANCE)
+            .count();
+        if (matching as f64) < line.len() as f64 * BORDER_FRACTION {
+            break;
+        }
+
+        len += 1;
+    }
+
+    len
+}
+
+/// Trims uniform borders, like letterboxing, caption padding and watermark strips, from each
+/// side of an image. Images that are nothing but border, or empty, are left as they are.
+pub fn trim_borders(gray: &GrayImage) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    if width == 0 || height == 0 {
+        return gray.clone();
+    }
+
+    let row = |y| (0..width).map(|x| gray.get_pixel(x, y)[0]).collect();
+    let column = |x| (0..height).map(|y| gray.get_pixel(x, y)[0]).collect();
+
+    let top = border_len((0..height).map(row));
+    let bottom = border_len((0..height).rev().map(row));
+    let left = border_len((0..width).map(column));
+    let right = border_len((0..width).rev().map(column));
+
+    if top + bottom >= height || left + right >= width {
+        return gray.clone();
+    }
+
+    imageops::crop_imm(gray, left, top, width - left - right, height - top - bottom).to_image()
+}
+
+/// A way reposters flip or rotate an image to keep it from matching the original
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Transform {
+    FlipHorizontal,
+    FlipVertical,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+pub static TRANSFORMS: [Transform; 5] = [
+    Transform::FlipHorizontal,
+    Transform::FlipVertical,
+    Transform::Rotate90,
+    Transform::Rotate180,
+    Transform::Rotate270,
+];
+
+impl Transform {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transform::FlipHorizontal => "flip_horizontal",
+            Transform::FlipVertical => "flip_vertical",
+            Transform::Rotate90 => "rotate_90",
+            Transform::Rotate180 => "rotate_180",
+            Transform::Rotate270 => "rotate_270",
+        }
+    }
+
+    pub fn apply(&self, gray: &GrayImage) -> GrayImage {
+        match self {
+            Transform::FlipHorizontal => imageops::flip_horizontal(gray),
+            Transform::FlipVertical => imageops::flip_vertical(gray),
+            Transform::Rotate90 => imageops::rotate90(gray),
+            Transform::Rotate180 => imageops::rotate180(gray),
+            Transform::Rotate270 => imageops::rotate270(gray),
+        }
+    }
+
+    /// The dHash of each transform of an image. They're taken from a 64x64 thumbnail, which
+    /// keeps them cheap and is plenty for the 9x8 one dHash uses.
+    pub fn dhashes(gray: &GrayImage) -> Vec<(Transform, Hash)> {
+        let small = imageops::thumbnail(gray, 64, 64);
+        TRANSFORMS
+            .iter()
+            .map(|transform| (*transform, DHash.hash(&transform.apply(&small))))
+            .collect()
+    }
+}
+
+/// Animations are decoded up to this many frames, which bounds the time spent on long ones
+const MAX_DECODED_FRAMES: usize = 500;
+
+/// Animations are also decoded up to this many pixels over all their frames, since each frame
+/// is decoded at the full size of the animation
+const MAX_DECODED_PIXELS: u64 = 100_000_000;
+
+/// How many of an animation's frames are hashed and stored
+pub const SAMPLED_FRAMES: usize = 8;
+
+/// A frame starts a new scene when its dHash is further than this from the scene's first frame
+const SCENE_DISTANCE: u32 = 4;
+
+/// Decodes each frame of an animated GIF, WebP or PNG. Other formats give `None`.
+fn decode_frames(image: &[u8]) -> Option<Frames<'_>> {
+    let cursor = Cursor::new(image);
+    match guess_format(image).ok()? {
+        ImageFormat::Gif => Some(GifDecoder::new(cursor).ok()?.into_frames()),
+        ImageFormat::WebP => Some(WebPDecoder::new(cursor).ok()?.into_frames()),
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(cursor).ok()?;
+            if decoder.is_apng() {
+                Some(decoder.apng().into_frames())
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Picks up to `SAMPLED_FRAMES` representative frames of an animation, giving each one's index
+/// and dHash. The first frame of every scene is a candidate, and when there are too many, those
+/// with the lowest hashes are kept. That choice depends only on the frames themselves, so clips
+/// cut from the same animation tend to keep the same ones. Stills give no frames.
+pub fn sample_frames(frames: impl Iterator<Item = GrayImage>) -> Vec<(u32, Hash)> {
+    let mut scenes = Vec::<(u32, Hash)>::new();
+    let mut count = 0;
+    for (index, frame) in frames.take(MAX_DECODED_FRAMES).enumerate() {
+        let hash = DHash.hash(&frame);
+        if !matches!(scenes.last(), Some((_, scene)) if distance(*scene, hash) <= SCENE_DISTANCE) {
+            scenes.push((index as u32, hash));
+        }
+        count += 1;
+    }
+
+    if count < 2 {
+        return Vec::new();
+    }
+
+    scenes.sort_by_key(|(_, hash)| hash.0);
+    scenes.truncate(SAMPLED_FRAMES);
+    scenes.sort_by_key(|(index, _)| *index);
+    scenes
+}
+
+/// A perceptual hash of a grayscale image, packed into 64 bits
+pub trait HashAlgorithm: Sync {
+    /// The name searches refer to it by
+    fn name(&self) -> &'static str;
+
+    /// The column of `images` and `image_cache` it's stored in
+    fn column(&self) -> &'static str;
+
+    fn hash(&self, gray: &GrayImage) -> Hash;
+}
+
+/// Whether each pixel of an 8x8 image is brighter than its neighbor to the right
+pub struct DHash;
+
+/// Whether each pixel of an 8x8 image is brighter than the mean
+pub struct AHash;
+
+/// Whether each of the lowest 8x8 frequencies of a 32x32 image's discrete cosine transform
+/// is above their median. Flat graphics that dHash can't tell apart still differ here.
+pub struct PHash;
+
+/// Whether each coefficient of the 8x8 approximation band of a 64x64 image's Haar wavelet
+/// transform is above their median, once the coarsest approximation is removed like imagehash
+/// does. Unlike aHash's mean, the median splits the image evenly however its brightness is skewed.
+pub struct WHash;
+
+/// Every algorithm images are hashed with. dHash comes first, as the one searches are indexed by.
+pub static ALGORITHMS: [&dyn HashAlgorithm; 4] = [&DHash, &AHash, &PHash, &WHash];
+
+impl fmt::Debug for dyn HashAlgorithm {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+pub fn algorithm(name: &str) -> Option<&'static dyn HashAlgorithm> {
+    ALGORITHMS
+        .iter()
+        .copied()
+        .find(|algorithm| algorithm.name() == name)
+}
+
+/// Sets bit `x + y * 8` for each value above `threshold`
+fn threshold_bits(values: &[f64; 64], threshold: f64) -> Hash {
+    Hash(
+        values
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| **value > threshold)
+            .fold(0, |hash, (i, _)| hash | 1 << i),
+    )
+}
+
+fn median(values: &[f64; 64]) -> f64 {
+    let mut sorted = *values;
+    sorted.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    (sorted[31] + sorted[32]) / 2.0
+}
+
+fn pixels<const N: usize>(gray: &GrayImage) -> [[f64; N]; N] {
+    let small = imageops::thumbnail(gray, N as u32, N as u32);
+    let mut pixels = [[0.0; N]; N];
+    for (x, y, pixel) in small.enumerate_pixels() {
+        pixels[y as usize][x as usize] = pixel[0] as f64;
+    }
+    pixels
+}
+
+/// One level of the 2D Haar wavelet transform of the top-left `size`x`size` of `values`,
+/// leaving the approximation band in the top-left quarter and the detail bands in the others
+fn haar_forward(values: &mut [[f64; 64]; 64], size: usize) {
+    let half = size / 2;
+    let mut out = [[0.0; 64]; 64];
+    for y in 0..half {
+        for x in 0..half {
+            let (a, b) = (values[2 * y][2 * x], values[2 * y][2 * x + 1]);
+            let (c, d) = (values[2 * y + 1][2 * x], values[2 * y + 1][2 * x + 1]);
+            out[y][x] = (a + b + c + d) / 2.0;
+            out[y][x + half] = (a - b + c - d) / 2.0;
+            out[y + half][x] = (a + b - c - d) / 2.0;
+            out[y + half][x + half] = (a - b - c + d) / 2.0;
+        }
+    }
+    for (row, out) in values.iter_mut().zip(out.iter()).take(size) {
+        row[..size].copy_from_slice(&out[..size]);
+    }
+}
+
+/// Undoes `haar_forward` on the top-left `size`x`size` of `values`
+fn haar_inverse(values: &mut [[f64; 64]; 64], size: usize) {
+    let half = size / 2;
+    let mut out = [[0.0; 64]; 64];
+    for y in 0..half {
+        for x in 0..half {
+            let (ll, h) = (values[y][x], values[y][x + half]);
+            let (v, d) = (values[y + half][x], values[y + half][x + half]);
+            out[2 * y][2 * x] = (ll + h + v + d) / 2.0;
+            out[2 * y][2 * x + 1] = (ll - h + v - d) / 2.0;
+            out[2 * y + 1][2 * x] = (ll + h - v - d) / 2.0;
+            out[2 * y + 1][2 * x + 1] = (ll - h - v + d) / 2.0;
+        }
+    }
+    for (row, out) in values.iter_mut().zip(out.iter()).take(size) {
+        row[..size].copy_from_slice(&out[..size]);
+    }
+}
+
+impl HashAlgorithm for DHash {
+    fn name(&self) -> &'static str {
+        "dhash"
+    }
+
+    fn column(&self) -> &'static str {
+        "hash"
+    }
+
+    fn hash(&self, gray: &GrayImage) -> Hash {
+        let small_img = imageops::thumbnail(gray, 9, 8);
+
+        let mut hash: u64 = 0;
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let bit = ((small_img.get_pixel(x, y)[0] > small_img.get_pixel(x + 1, y)[0])
+                    as u64)
+                    << (x + y * 8);
+                hash |= bit;
+            }
+        }
+
+        Hash(hash)
+    }
+}
+
+impl HashAlgorithm for AHash {
+    fn name(&self) -> &'static str {
+        "ahash"
+    }
+
+    fn column(&self) -> &'static str {
+        "ahash"
+    }
+
+    fn hash(&self, gray: &GrayImage) -> Hash {
+        let values: [f64; 64] = pixels::<8>(gray).concat().try_into().unwrap();
+        threshold_bits(&values, values.iter().sum::<f64>() / 64.0)
+    }
+}
 
-    let mut hash: u64 = 0;
+impl HashAlgorithm for PHash {
+    fn name(&self) -> &'static str {
+        "phash"
+    }
+
+    fn column(&self) -> &'static str {
+        "phash"
+    }
+
+    fn hash(&self, gray: &GrayImage) -> Hash {
+        let pixels = pixels::<32>(gray);
+        let basis = |n: usize, k: usize| {
+            ((2 * n + 1) as f64 * k as f64 * std::f64::consts::PI / 64.0).cos()
+        };
+
+        // Only the 8x8 lowest frequencies are needed, so each dimension is transformed
+        // separately and only that far
+        let mut rows = [[0.0; 8]; 32];
+        for (row, pixels) in rows.iter_mut().zip(pixels.iter()) {
+            for (u, value) in row.iter_mut().enumerate() {
+                *value = pixels
+                    .iter()
+                    .enumerate()
+                    .map(|(x, p)| p * basis(x, u))
+                    .sum();
+            }
+        }
+
+        let mut values = [0.0; 64];
+        for v in 0..8 {
+            for u in 0..8 {
+                values[u + v * 8] = (0..32).map(|y| rows[y][u] * basis(y, v)).sum();
+            }
+        }
+
+        threshold_bits(&values, median(&values))
+    }
+}
+
+impl HashAlgorithm for WHash {
+    fn name(&self) -> &'static str {
+        "whash"
+    }
+
+    fn column(&self) -> &'static str {
+        "whash"
+    }
+
+    fn hash(&self, gray: &GrayImage) -> Hash {
+        let mut pixels = pixels::<64>(gray);
+
+        // Decomposing all the way down leaves a single approximation coefficient, the image's
+        // overall brightness, which is removed before building it back up
+        let mut size = 64;
+        while size > 1 {
+            haar_forward(&mut pixels, size);
+            size /= 2;
+        }
+        pixels[0][0] = 0.0;
+        while size < 64 {
+            size *= 2;
+            haar_inverse(&mut pixels, size);
+        }
+
+        // Three levels take 64x64 down to 8x8, and the detail bands they split off are dropped
+        for size in [64, 32, 16] {
+            haar_forward(&mut pixels, size);
+        }
+
+        let values: [f64; 64] = pixels[..8]
+            .iter()
+            .flat_map(|row| row[..8].iter().copied())
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap();
+        threshold_bits(&values, median(&values))
+    }
+}
+
+/// An image's hash under each algorithm. dHash is always known, but images hashed before
+/// the other algorithms were added may lack them.
+#[derive(Clone, Debug)]
+pub struct Hashes {
+    pub dhash: Hash,
+    others: BTreeMap<&'static str, Hash>,
+    pub wide: Option<WideHash>,
+    /// The dHash once borders are trimmed, to match copies that added or removed them
+    pub trimmed: Option<Hash>,
+    /// The dHash of each flipped and rotated version. These aren't stored, and are only
+    /// computed when a search asks for them.
+    pub transforms: Vec<(Transform, Hash)>,
+    /// The index and dHash of each sampled frame of an animation, or `None` when they aren't
+    /// known. They're only sampled when ingesting or when a search asks for them, and are
+    /// stored apart from the other hashes, in `image_frames`.
+    pub frames: Option<Vec<(u32, Hash)>>,
+}
+
+impl Hashes {
+    /// Only a dHash, like one given to a search directly
+    pub fn new(dhash: Hash) -> Self {
+        Self {
+            dhash,
+            others: BTreeMap::new(),
+            wide: None,
+            trimmed: None,
+            transforms: Vec::new(),
+            frames: None,
+        }
+    }
+
+    pub fn of(gray: &GrayImage) -> Self {
+        let mut hashes = Self::new(DHash.hash(gray));
+        for algorithm in &ALGORITHMS[1..] {
+            hashes.others.insert(algorithm.name(), algorithm.hash(gray));
+        }
+        hashes.wide = Some(WideHash::of(gray));
+        hashes.trimmed = Some(DHash.hash(&trim_borders(gray)));
+        hashes
+    }
+
+    /// Reads each algorithm's column from a row of `images` or `image_cache`
+    pub fn from_row(row: &Row) -> Self {
+        let mut hashes = Self::new(Hash(row.get::<_, i64>(DHash.column()) as u64));
+        for algorithm in &ALGORITHMS[1..] {
+            if let Some(hash) = row.get::<_, Option<i64>>(algorithm.column()) {
+                hashes.others.insert(algorithm.name(), Hash(hash as u64));
+            }
+        }
+        hashes.wide = row.get("wide_hash");
+        hashes.trimmed = row
+            .get::<_, Option<i64>>("trimmed_hash")
+            .map(|hash| Hash(hash as u64));
+        hashes
+    }
+
+    pub fn get(&self, algorithm: &dyn HashAlgorithm) -> Option<Hash> {
+        if algorithm.name() == DHash.name() {
+            Some(self.dhash)
+        } else {
+            self.others.get(algorithm.name()).copied()
+        }
+    }
+
+    /// Only the hashes a search uses, to carry on to its later pages: the dHash, the wide and
+    /// trimmed hashes, the algorithms it requires to agree, and the transforms and frames only
+    /// if they're searched
+    pub fn narrowed(
+        &self,
+        agree: &[&'static dyn HashAlgorithm],
+        transforms: bool,
+        frames: bool,
+    ) -> Self {
+        Hashes {
+            dhash: self.dhash,
+            others: self
+                .others
+                .iter()
+                .filter(|(name, _)| agree.iter().any(|algorithm| algorithm.name() == **name))
+                .map(|(name, hash)| (*name, *hash))
+                .collect(),
+            wide: self.wide,
+            trimmed: self.trimmed,
+            transforms: if transforms {
+                self.transforms.clone()
+            } else {
+                Vec::new()
+            },
+            frames: self.frames.clone().filter(|_| frames),
+        }
+    }
+
+    /// Every algorithm's column, in the order of `ALGORITHMS`, then `wide_hash` and `trimmed_hash`
+    pub fn columns() -> String {
+        ALGORITHMS
+            .iter()
+            .map(|algorithm| algorithm.column())
+            .chain(vec!["wide_hash", "trimmed_hash"])
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Every hash, in the order of `columns`
+    pub fn values(&self) -> Vec<Box<dyn types::ToSql + Sync + Send>> {
+        ALGORITHMS
+            .iter()
+            .map(|algorithm| Box::new(self.get(*algorithm)) as Box<dyn types::ToSql + Sync + Send>)
+            .chain(vec![
+                Box::new(self.wide) as Box<dyn types::ToSql + Sync + Send>,
+                Box::new(self.trimmed),
+            ])
+            .collect()
+    }
+}
+
+/// A bare dHash when that's all there is, otherwise `algorithm:hash` pairs separated by commas
+impl Display for Hashes {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.others.is_empty()
+            && self.wide.is_none()
+            && self.trimmed.is_none()
+            && self.transforms.is_empty()
+            && self.frames.iter().flatten().next().is_none()
+        {
+            return Display::fmt(&self.dhash, f);
+        }
+
+        let pairs = ALGORITHMS
+            .iter()
+            .filter_map(|algorithm| {
+                self.get(*algorithm)
+                    .map(|hash| format!("{}:{}", algorithm.name(), hash))
+            })
+            .chain(self.wide.map(|wide| format!("wide:{}", wide)))
+            .chain(self.trimmed.map(|trimmed| format!("trimmed:{}", trimmed)))
+            .chain(
+                self.transforms
+                    .iter()
+                    .map(|(transform, hash)| format!("{}:{}", transform.name(), hash)),
+            )
+            .chain(
+                self.frames
+                    .iter()
+                    .flatten()
+                    .map(|(index, hash)| format!("frame{}:{}", index, hash)),
+            )
+            .collect::<Vec<_>>();
+        f.write_str(&pairs.join(","))
+    }
+}
 
-    for y in 0..8 {
-        for x in 0..8 {
-            let bit = ((small_img.get_pixel(x, y)[0] > small_img.get_pixel(x + 1, y)[0]) as u64)
-                << (x + y * 8);
-            hash |= bit;
+impl FromStr for Hashes {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.contains(':') {
+            return Ok(Hashes::new(s.parse()?));
+        }
+
+        let mut dhash = None;
+        let mut others = BTreeMap::new();
+        let mut wide = None;
+        let mut trimmed = None;
+        let mut transforms = Vec::new();
+        let mut frames = None;
+        let mut seen = BTreeSet::new();
+        for pair in s.split(',') {
+            let (name, hash) = pair
+                .split_once(':')
+                .ok_or_else(|| failure::format_err!("Missing algorithm: {}", pair))?;
+            let name = name.trim();
+            if !seen.insert(name) {
+                return Err(failure::format_err!("Repeated hash: {}", name));
+            }
+            if name == "wide" {
+                wide = Some(hash.parse()?);
+                continue;
+            }
+            if name == "trimmed" {
+                trimmed = Some(hash.parse()?);
+                continue;
+            }
+            if let Some(transform) = TRANSFORMS.iter().find(|t| t.name() == name) {
+                transforms.push((*transform, hash.parse()?));
+                continue;
+            }
+            if let Some(Ok(index)) = name.strip_prefix("frame").map(str::parse) {
+                let frames = frames.get_or_insert_with(Vec::new);
+                if frames.len() == SAMPLED_FRAMES {
+                    return Err(failure::format_err!(
+                        "More than {} frames: {}",
+                        SAMPLED_FRAMES,
+                        s
+                    ));
+                }
+                frames.push((index, hash.parse()?));
+                continue;
+            }
+            let algorithm = algorithm(name)
+                .ok_or_else(|| failure::format_err!("Unknown algorithm: {}", name))?;
+            let hash = hash.parse()?;
+            if algorithm.name() == DHash.name() {
+                dhash = Some(hash);
+            } else {
+                others.insert(algorithm.name(), hash);
+            }
         }
+
+        Ok(Hashes {
+            dhash: dhash.ok_or_else(|| failure::format_err!("Missing dhash: {}", s))?,
+            others,
+            wide,
+            trimmed,
+            transforms,
+            frames,
+        })
     }
+}
 
-    Ok(Hash(hash))
+pub fn dhash(img: DynamicImage) -> Result<Hash, UserError> {
+    Ok(DHash.hash(&grayscale(&img)?))
 }
 
 pub fn distance(a: Hash, b: Hash) -> u32 {
     (a.0 ^ b.0).count_ones()
 }
 
+fn load(image: &[u8]) -> Result<DynamicImage, UserError> {
+    load_from_memory(image).map_err(map_ue_save!("invalid image", "image_invalid"))
+}
+
 pub fn hash_from_memory(image: &[u8]) -> Result<Hash, UserError> {
-    dhash(load_from_memory(&image).map_err(map_ue_save!("invalid image", "image_invalid"))?)
+    dhash(load(image)?)
+}
+
+/// Every hash of an image along with, if `transforms`, the hashes of its flips and rotations
+/// and, if `frames`, those of its sampled frames when it's animated
+pub fn hashes_from_memory(
+    image: &[u8],
+    transforms: bool,
+    frames: bool,
+) -> Result<Hashes, UserError> {
+    let gray = grayscale(&load(image)?)?;
+    let mut hashes = Hashes::of(&gray);
+    if transforms {
+        hashes.transforms = Transform::dhashes(&gray);
+    }
+    if frames {
+        hashes.frames = Some(
+            decode_frames(image)
+                .map(|frames| {
+                    let mut pixels = 0;
+                    sample_frames(
+                        frames
+                            .map_while(Result::ok)
+                            .take_while(|frame| {
+                                let (width, height) = frame.buffer().dimensions();
+                                pixels += u64::from(width) * u64::from(height);
+                                pixels <= MAX_DECODED_PIXELS
+                            })
+                            .filter_map(|frame| {
+                                grayscale(&DynamicImage::ImageRgba8(frame.into_buffer())).ok()
+                            }),
+                    )
+                })
+                .unwrap_or_default(),
+        );
+    }
+    Ok(hashes)
+}
+
+/// `hashes_from_memory` on the blocking thread pool, as decoding an animation can take a while
+pub async fn spawn_hashes_from_memory(
+    image: impl AsRef<[u8]> + Send + 'static,
+    transforms: bool,
+    frames: bool,
+) -> Result<Hashes, UserError> {
+    tokio::task::spawn_blocking(move || hashes_from_memory(image.as_ref(), transforms, frames))
+        .await
+        .map_err(|_e| ue_save!("image panicked!", "image_panic", Source::User))?
 }
 
 fn rgb_to_luma(r: u8, g: u8, b: u8) -> u8 {
     ((u32::from(r) * 2126 + u32::from(g) * 7152 + u32::from(b) * 722) / 10000) as u8
 }
 
-pub fn grayscale(img: &DynamicImage) -> Result<DynamicImage, UserError> {
+pub fn grayscale(img: &DynamicImage) -> Result<GrayImage, UserError> {
     let width = img.width();
     let height = img.height();
 
     use DynamicImage::*;
-    Ok(ImageLuma8(match img {
+    Ok(match img {
         ImageLuma8(gray) => gray.clone(),
         ImageLumaA8(gray_alpha) => GrayImage::from_vec(
             width,
@@ -93,5 +870,381 @@ pub fn grayscale(img: &DynamicImage) -> Result<DynamicImage, UserError> {
                 Source::User
             ))
         }
-    }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hash() {
+        let expected = 0xb4e2_5a3c_0f0f_1e2d;
+
+        assert_eq!("13034079485560823341".parse::<Hash>().unwrap().0, expected);
+        assert_eq!(
+            (expected as i64).to_string().parse::<Hash>().unwrap().0,
+            expected
+        );
+        assert_eq!("0xb4e25a3c0f0f1e2d".parse::<Hash>().unwrap().0, expected);
+        assert_eq!("0XB4E25A3C0F0F1E2D".parse::<Hash>().unwrap().0, expected);
+        assert_eq!("tOJaPA8PHi0=".parse::<Hash>().unwrap().0, expected);
+        assert_eq!("tOJaPA8PHi0".parse::<Hash>().unwrap().0, expected);
+        assert_eq!("0".parse::<Hash>().unwrap().0, 0);
+    }
+
+    #[test]
+    fn parse_all_digit_hash() {
+        assert_eq!(
+            "1234567890123456".parse::<Hash>().unwrap().0,
+            1_234_567_890_123_456
+        );
+        assert_eq!(
+            "0x1234567890123456".parse::<Hash>().unwrap().0,
+            0x1234_5678_9012_3456
+        );
+        assert!("B4E25A3C0F0F1E2D".parse::<Hash>().is_err());
+
+        let base64 = base64::encode(1_234_567_890u64.to_be_bytes());
+        assert_eq!(base64.parse::<Hash>().unwrap().0, 1_234_567_890);
+    }
+
+    fn waves(width: u32, height: u32, invert: bool) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| {
+            let (x, y) = (x as f64 / width as f64, y as f64 / height as f64);
+            let value = 128.0 + 60.0 * (x * 9.0).sin() + 60.0 * (y * 5.0 + x * 2.0).cos();
+            image::Luma([if invert { 255.0 - value } else { value } as u8])
+        })
+    }
+
+    #[test]
+    fn algorithms() {
+        let original = Hashes::of(&waves(640, 480, false));
+        let resized = Hashes::of(&waves(200, 150, false));
+        let inverted = Hashes::of(&waves(640, 480, true));
+
+        for algorithm in &ALGORITHMS {
+            let hash = original.get(*algorithm).unwrap();
+            assert!(
+                distance(hash, resized.get(*algorithm).unwrap()) <= 4,
+                "{} of a resized image",
+                algorithm.name()
+            );
+            assert!(
+                distance(hash, inverted.get(*algorithm).unwrap()) >= 32,
+                "{} of an inverted image",
+                algorithm.name()
+            );
+        }
+
+        assert_eq!(algorithm("phash").unwrap().column(), "phash");
+        assert_eq!(algorithm("dhash").unwrap().column(), "hash");
+        assert!(algorithm("md5").is_none());
+    }
+
+    #[test]
+    fn haar_round_trip() {
+        let mut values = [[0.0; 64]; 64];
+        for (y, row) in values.iter_mut().enumerate() {
+            for (x, value) in row.iter_mut().enumerate() {
+                *value = ((x * 7 + y * 13) % 31) as f64;
+            }
+        }
+        let original = values;
+
+        haar_forward(&mut values, 64);
+        haar_forward(&mut values, 32);
+        assert_eq!(
+            values[0][0],
+            (0..4)
+                .flat_map(|y| (0..4).map(move |x| original[y][x]))
+                .sum::<f64>()
+                / 4.0
+        );
+
+        haar_inverse(&mut values, 32);
+        haar_inverse(&mut values, 64);
+        assert_eq!(values, original);
+    }
+
+    #[test]
+    fn whash_differs_from_ahash() {
+        // 8x8 blocks mostly dark, so the mean is far above the median
+        let image = GrayImage::from_fn(64, 64, |x, y| {
+            let block = x / 8 + y / 8 * 8;
+            image::Luma([match block {
+                0..=32 => 10,
+                33..=48 => 50,
+                _ => 255,
+            }])
+        });
+
+        let whash = WHash.hash(&image);
+        let ahash = AHash.hash(&image);
+
+        assert_eq!(whash.0.count_ones(), 31);
+        assert_eq!(ahash.0.count_ones(), 15);
+        assert_eq!(distance(whash, ahash), 16);
+    }
+
+    #[test]
+    fn transforms() {
+        // Unlike `waves`, this looks different under every flip and rotation
+        let image = GrayImage::from_fn(640, 480, |x, y| {
+            let (x, y) = (x as f64 / 640.0, y as f64 / 480.0);
+            image::Luma([
+                (128.0 + 60.0 * (x * 7.0 + y * 3.0).sin() + 50.0 * (y * y * 9.0).cos()) as u8,
+            ])
+        });
+        let hashes = Hashes::of(&image);
+        assert!(hashes.transforms.is_empty());
+        let transforms = Transform::dhashes(&image);
+
+        for transform in &TRANSFORMS {
+            let transformed = DHash.hash(&transform.apply(&image));
+            let (_, hash) = transforms.iter().find(|(t, _)| t == transform).unwrap();
+            assert!(
+                distance(*hash, transformed) <= 4,
+                "{} of the query",
+                transform.name()
+            );
+            assert!(
+                distance(hashes.dhash, transformed) > 4,
+                "{} of the query matched untransformed",
+                transform.name()
+            );
+        }
+    }
+
+    /// Surrounds an image with borders of the given widths and brightness
+    fn pad(
+        image: &GrayImage,
+        top: u32,
+        bottom: u32,
+        left: u32,
+        right: u32,
+        color: u8,
+    ) -> GrayImage {
+        let mut padded = GrayImage::from_pixel(
+            left + image.width() + right,
+            top + image.height() + bottom,
+            image::Luma([color]),
+        );
+        imageops::replace(&mut padded, image, left as i64, top as i64);
+        padded
+    }
+
+    #[test]
+    fn trim_borders_padded() {
+        let original = waves(640, 480, false);
+        let hash = DHash.hash(&original);
+
+        let letterboxed = pad(&original, 90, 90, 0, 0, 0);
+        let bordered = pad(&original, 24, 24, 24, 24, 255);
+        let pillarboxed = pad(&original, 0, 0, 160, 160, 10);
+
+        // A meme caption: a white bar above the image with dark text across a fifth of it
+        let mut captioned = pad(&original, 120, 0, 0, 0, 255);
+        for y in 40..80 {
+            for x in (0..640).filter(|x| x % 50 < 10) {
+                captioned.put_pixel(x, y, image::Luma([0]));
+            }
+        }
+
+        for (padded, name) in [
+            (letterboxed, "letterboxed"),
+            (bordered, "bordered"),
+            (pillarboxed, "pillarboxed"),
+            (captioned, "captioned"),
+        ] {
+            assert!(
+                distance(hash, DHash.hash(&padded)) > 4,
+                "{} matched without trimming",
+                name
+            );
+            assert_eq!(trim_borders(&padded).dimensions(), (640, 480), "{}", name);
+            assert_eq!(
+                Hashes::of(&padded).trimmed.unwrap().0,
+                hash.0,
+                "{} trimmed",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn trim_borders_empty() {
+        for (width, height) in [(0, 0), (0, 10), (10, 0)] {
+            let empty = GrayImage::new(width, height);
+            assert_eq!(trim_borders(&empty).dimensions(), (width, height));
+        }
+    }
+
+    #[test]
+    fn trim_borders_unpadded() {
+        let original = waves(640, 480, false);
+        assert_eq!(trim_borders(&original).dimensions(), (640, 480));
+
+        let blank = GrayImage::from_pixel(100, 100, image::Luma([255]));
+        assert_eq!(trim_borders(&blank).dimensions(), (100, 100));
+    }
+
+    #[test]
+    fn wide_hash() {
+        let original = WideHash::of(&waves(640, 480, false));
+        let resized = WideHash::of(&waves(200, 150, false));
+        let inverted = WideHash::of(&waves(640, 480, true));
+
+        assert!(original.distance(&resized) <= 4 * WIDE_SCALE as u32);
+        assert!(original.distance(&inverted) >= 128);
+        assert_eq!(original.to_string().len(), 64);
+        assert_eq!(original.to_string().parse::<WideHash>().unwrap(), original);
+        assert!("abcd".parse::<WideHash>().is_err());
+    }
+
+    #[test]
+    fn hashes_round_trip() {
+        let hashes = Hashes::of(&waves(64, 64, false));
+        let parsed = hashes.to_string().parse::<Hashes>().unwrap();
+        for algorithm in &ALGORITHMS {
+            assert_eq!(
+                parsed.get(*algorithm).unwrap().0,
+                hashes.get(*algorithm).unwrap().0
+            );
+        }
+        assert_eq!(parsed.wide, hashes.wide);
+        assert_eq!(parsed.to_string(), hashes.to_string());
+
+        assert_eq!(Hashes::new(Hash(5)).to_string(), "5");
+        assert_eq!("5".parse::<Hashes>().unwrap().dhash.0, 5);
+        assert!("phash:5".parse::<Hashes>().is_err());
+        assert!("dhash:5,md5:3".parse::<Hashes>().is_err());
+        assert!("dhash:5,dhash:6".parse::<Hashes>().is_err());
+        assert!("dhash:5,rotate_90:3,rotate_90:3".parse::<Hashes>().is_err());
+
+        let frames = (0..=SAMPLED_FRAMES)
+            .map(|index| format!("frame{}:{}", index, index))
+            .collect::<Vec<_>>();
+        assert!(format!("dhash:5,{}", frames[1..].join(","))
+            .parse::<Hashes>()
+            .is_ok());
+        assert!(format!("dhash:5,{}", frames.join(","))
+            .parse::<Hashes>()
+            .is_err());
+    }
+
+    #[test]
+    fn narrowed_hashes() {
+        let image = waves(64, 64, false);
+        let mut hashes = Hashes::of(&image);
+        hashes.transforms = Transform::dhashes(&image);
+        hashes.frames = Some(vec![(0, Hash(1)), (4, Hash(2))]);
+
+        let narrowed = hashes.narrowed(&[&PHash], false, true).to_string();
+        assert!(narrowed.contains("phash:"));
+        assert!(!narrowed.contains("ahash:"));
+        assert!(!narrowed.contains("rotate_90:"));
+        assert!(narrowed.contains("frame4:2"));
+
+        let narrowed = hashes.narrowed(&[], true, false);
+        assert_eq!(narrowed.transforms.len(), TRANSFORMS.len());
+        assert!(narrowed.frames.is_none());
+        assert!(narrowed.get(&AHash).is_none());
+    }
+
+    /// A distinct image for each scene of an animation
+    fn scene(n: u32) -> GrayImage {
+        GrayImage::from_fn(64, 48, |x, y| {
+            let (x, y) = (x as f64 / 64.0, y as f64 / 48.0);
+            let n = n as f64;
+            let value = 128.0 + 60.0 * (x * (n + 2.0) + y * n).sin() + 50.0 * (y * 7.0 - n).cos();
+            image::Luma([value as u8])
+        })
+    }
+
+    /// Each scene, repeated for a few frames
+    fn scenes(scenes: std::ops::Range<u32>) -> Vec<GrayImage> {
+        scenes.flat_map(|n| vec![scene(n); 4]).collect()
+    }
+
+    fn hashes_of_frames(frames: &[(u32, Hash)]) -> Vec<u64> {
+        frames.iter().map(|(_, hash)| hash.0).collect()
+    }
+
+    #[test]
+    fn sample_frames_scenes() {
+        let sampled = sample_frames(scenes(0..3).into_iter());
+        assert_eq!(
+            sampled.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            vec![0, 4, 8]
+        );
+
+        assert!(sample_frames(vec![scene(0)].into_iter()).is_empty());
+    }
+
+    #[test]
+    fn sample_frames_clips() {
+        let original = hashes_of_frames(&sample_frames(scenes(0..12).into_iter()));
+        assert_eq!(original.len(), SAMPLED_FRAMES);
+
+        let clip = hashes_of_frames(&sample_frames(scenes(3..12).into_iter()));
+        assert!(clip.len() <= SAMPLED_FRAMES);
+        assert!(
+            clip.iter().filter(|hash| original.contains(hash)).count() >= SAMPLED_FRAMES - 3,
+            "{:?} and {:?}",
+            original,
+            clip
+        );
+    }
+
+    #[test]
+    fn hashes_from_gif() {
+        let mut gif = Vec::new();
+        image::codecs::gif::GifEncoder::new(&mut gif)
+            .encode_frames(
+                scenes(0..3)
+                    .into_iter()
+                    .map(|frame| image::Frame::new(DynamicImage::ImageLuma8(frame).to_rgba8())),
+            )
+            .unwrap();
+
+        assert!(hashes_from_memory(&gif, false, false)
+            .unwrap()
+            .frames
+            .is_none());
+
+        let hashes = hashes_from_memory(&gif, false, true).unwrap();
+        let frames = hashes.frames.clone().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert!(distance(frames[0].1, hashes.dhash) <= 2);
+        assert!(distance(frames[1].1, DHash.hash(&scene(1))) <= 4);
+
+        let parsed = hashes.to_string().parse::<Hashes>().unwrap();
+        assert_eq!(
+            hashes_of_frames(&parsed.frames.unwrap()),
+            hashes_of_frames(&frames)
+        );
+
+        let mut png = Vec::new();
+        DynamicImage::ImageLuma8(scene(0))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        assert_eq!(
+            hashes_from_memory(&png, false, true)
+                .unwrap()
+                .frames
+                .unwrap()
+                .len(),
+            0
+        );
+    }
+
+    #[test]
+    fn parse_bad_hash() {
+        assert!("".parse::<Hash>().is_err());
+        assert!("0xnothex".parse::<Hash>().is_err());
+        assert!("99999999999999999999".parse::<Hash>().is_err());
+        assert!("dGVzdA==".parse::<Hash>().is_err());
+        assert!("not a hash!".parse::<Hash>().is_err());
+    }
 }
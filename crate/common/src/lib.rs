@@ -13,18 +13,37 @@ use std::fmt;
 use std::string::ToString;
 use std::time::Duration;
 
+mod api_keys;
+pub use api_keys::*;
+
 mod banned;
 pub use banned::*;
 
+mod denylist;
+pub use denylist::*;
+
 mod getter;
 pub use getter::*;
 
 mod hash;
 pub use hash::*;
 
+pub mod metrics;
+
+mod moderation;
+pub use moderation::*;
+
+mod rate_limit;
+pub use rate_limit::*;
+
+mod reddit;
+pub use reddit::*;
+
 mod submission;
 pub use submission::*;
 
+pub mod watch;
+
 pub use tracing::{debug, error, info, info_span, warn};
 
 pub const USER_AGENT: &str = concat!("Tidder ", env!("CARGO_PKG_VERSION"));
@@ -44,6 +63,8 @@ pub static COMMON_HEADERS: Lazy<HeaderMap<HeaderValue>> = Lazy::new(|| {
     headers.insert(header::USER_AGENT, HeaderValue::from_static(USER_AGENT));
     headers
 });
+/// Only for URLs from trusted sources like the config or Reddit; user-supplied ones go through
+/// `FetchPolicy::Public.client()`
 pub static REQW_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
     reqwest::Client::builder()
         .timeout(Duration::from_secs(30))
@@ -352,18 +373,6 @@ pub const IMAGE_MIMES_NO_WEBP: [&str; 12] = [
     "image/vnd.radiance",
 ];
 
-#[derive(Deserialize, Serialize)]
-pub struct CommonImages {
-    pub as_of: chrono::DateTime<chrono::Utc>,
-    pub common_images: Vec<CommonImage>,
-}
-
-#[derive(Deserialize, Serialize)]
-pub struct CommonImage {
-    pub num: u64,
-    pub link: String,
-}
-
 #[derive(Copy, Debug, Clone, Eq, PartialEq)]
 pub enum HashDest {
     Images,
@@ -377,18 +386,30 @@ impl HashDest {
             HashDest::ImageCache => "image_cache",
         }
     }
+
+    /// Where the sampled frames of its animations are kept
+    pub fn frames_table_name(self) -> &'static str {
+        match self {
+            HashDest::Images => "image_frames",
+            HashDest::ImageCache => "image_cache_frames",
+        }
+    }
 }
 
-async fn get_existing(link: &str) -> Result<Option<(Hash, HashDest, i64)>, UserError> {
+async fn get_existing(link: &str) -> Result<Option<(Hashes, HashDest, i64)>, UserError> {
     let client = PG_POOL.get().await?;
 
     let stmt = client
         .prepare(
-            "SELECT hash, id, 'images' as table_name \
-             FROM images WHERE link = $1 \
-             UNION \
-             SELECT hash, id, 'image_cache' as table_name \
-             FROM image_cache WHERE link = $1",
+            format!(
+                "SELECT {0}, id, 'images' as table_name \
+                 FROM images WHERE link = $1 \
+                 UNION \
+                 SELECT {0}, id, 'image_cache' as table_name \
+                 FROM image_cache WHERE link = $1",
+                Hashes::columns()
+            )
+            .as_str(),
         )
         .await?;
 
@@ -396,7 +417,7 @@ async fn get_existing(link: &str) -> Result<Option<(Hash, HashDest, i64)>, UserE
 
     Ok(rows.first().map(|row| {
         (
-            Hash(row.get::<_, i64>("hash") as u64),
+            Hashes::from_row(row),
             match row.get("table_name") {
                 "images" => HashDest::Images,
                 "image_cache" => HashDest::ImageCache,
@@ -488,6 +509,10 @@ pub mod secrets {
     }
     #[derive(Debug, Deserialize)]
     pub struct Secrets {
+        /// Each admin's name and the hex SHA-256 digest of their password for the site's
+        /// admin area
+        #[serde(default)]
+        pub admins: std::collections::HashMap<String, String>,
         pub imgur: Imgur,
         pub postgres: deadpool_postgres::Config,
         pub reddit: Reddit,
@@ -515,26 +540,68 @@ pub mod config {
         pub count: usize,
     }
 
+    #[derive(Deserialize)]
+    pub struct BatchLimits {
+        pub max_inputs: usize,
+        pub concurrency: usize,
+        /// The most bytes a batch may upload in total. Each file is also held to
+        /// `RateLimits::max_upload`.
+        pub max_length: u64,
+    }
+
+    #[derive(Clone, Copy, Deserialize)]
+    pub struct BucketLimits {
+        pub capacity: u32,
+        pub per_minute: u32,
+    }
+
+    #[derive(Deserialize)]
+    pub struct RateLimits {
+        pub per_ip: BucketLimits,
+        pub per_key: BucketLimits,
+        /// The most bytes a single search may upload
+        pub max_upload: u64,
+        /// Whether the site is behind a proxy that sets `X-Forwarded-For`
+        pub trust_forwarded_for: bool,
+    }
+
     #[derive(Deserialize)]
     pub struct Config {
         pub banned: Vec<super::Banned>,
+        pub batch_limits: BatchLimits,
         pub custom_limits: std::collections::HashMap<String, Option<u32>>,
+        pub denylist_file: Option<String>,
         pub enable_imgur_api: bool,
         pub domains_in_flight_limit: u32,
         pub max_distance: u8,
         pub max_results: i64,
+        pub metrics_listeners: std::collections::HashMap<String, std::net::SocketAddr>,
         pub no_blacklist: Vec<String>,
+        pub rate_limits: RateLimits,
         pub worker_count: usize,
         pub state_file: String,
         pub time_limits: TimeLimits,
     }
 
     pub fn load() -> Result<Config, Error> {
-        ron::de::from_reader(std::fs::File::open(concat!(
+        let config: Config = ron::de::from_reader(std::fs::File::open(concat!(
             env!("CARGO_MANIFEST_DIR"),
             "/../tidder.ron"
-        ))?)
-        .map_err(Error::from)
+        ))?)?;
+
+        for (name, limits) in &[
+            ("per_ip", config.rate_limits.per_ip),
+            ("per_key", config.rate_limits.per_key),
+        ] {
+            if limits.capacity == 0 || limits.per_minute == 0 {
+                return Err(failure::format_err!(
+                    "rate_limits.{} needs a positive capacity and per_minute",
+                    name
+                ));
+            }
+        }
+
+        Ok(config)
     }
 }
 
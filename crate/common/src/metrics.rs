@@ -0,0 +1,151 @@
+//! Prometheus metrics shared by the site and the ingesters
+
+use super::*;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use std::convert::Infallible;
+
+/// Seconds taken by searches, `stage` being either just the `query` or the `total` request
+pub static SEARCH_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "tidder_search_seconds",
+        "Seconds taken by searches",
+        &["stage"]
+    )
+    .unwrap()
+});
+
+/// Hashes gotten, `source` being either the `cache` or a `request` to the image's host
+pub static HASH_GETS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!("tidder_hash_gets_total", "Hashes gotten", &["source"]).unwrap()
+});
+
+/// Posts saved without an image, by the kind of their `save_error`
+pub static SAVE_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "tidder_save_errors_total",
+        "Posts saved without an image",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+/// Requests refused for exceeding a rate limit, by the `limit` that was hit
+pub static RATE_LIMITED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "tidder_rate_limited_total",
+        "Requests refused for exceeding a rate limit",
+        &["limit"]
+    )
+    .unwrap()
+});
+
+pub static DOMAINS_IN_FLIGHT: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "tidder_domains_in_flight",
+        "Requests in flight to each host",
+        &["host"]
+    )
+    .unwrap()
+});
+
+pub static BLACKLIST_SIZE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!("tidder_blacklist_size", "Hosts blacklisted for timing out").unwrap()
+});
+
+pub static POSTS_PER_MINUTE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!("tidder_posts_per_minute", "Posts ingested per minute").unwrap()
+});
+
+/// Counts a post saved with a `save_error`, labeled without any detail after a colon
+/// so a rule's reason doesn't become its own series
+pub fn count_save_error(save_error: &str) {
+    let kind = save_error.split(':').next().unwrap_or(save_error);
+    SAVE_ERRORS.with_label_values(&[kind]).inc();
+}
+
+/// Sets how many requests are in flight to a host, dropping its series once there are none
+/// so that every host ever requested doesn't stay a series
+pub fn set_domains_in_flight(host: &str, in_flight: u32) {
+    if in_flight == 0 {
+        let _ = DOMAINS_IN_FLIGHT.remove_label_values(&[host]);
+    } else {
+        DOMAINS_IN_FLIGHT
+            .with_label_values(&[host])
+            .set(in_flight as i64);
+    }
+}
+
+/// Every metric in Prometheus' text format
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+/// Serves `render` at `GET /metrics` on the address configured for this binary in
+/// `metrics_listeners`, if any, answering anything else with a 404. The site's metrics are only
+/// served here, not on its public address.
+pub fn listen(name: &str) {
+    let addr = match CONFIG.metrics_listeners.get(name) {
+        Some(addr) => *addr,
+        None => return,
+    };
+
+    let make_service = make_service_fn(|_conn| async {
+        Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+            let response = if req.method() == Method::GET && req.uri().path() == "/metrics" {
+                Response::builder()
+                    .header(
+                        hyper::header::CONTENT_TYPE,
+                        TextEncoder::new().format_type(),
+                    )
+                    .body(Body::from(render()))
+            } else {
+                Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::empty())
+            };
+
+            Ok::<_, Infallible>(response.unwrap())
+        }))
+    });
+
+    info!("Serving metrics on http://{}", addr);
+
+    tokio::spawn(async move {
+        if let Err(e) = Server::bind(&addr).serve(make_service).await {
+            error!("metrics listener failed: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_error_kinds() {
+        count_save_error("banned: spam");
+        count_save_error("banned");
+        count_save_error("http_404");
+
+        assert_eq!(SAVE_ERRORS.with_label_values(&["banned"]).get(), 2);
+        assert!(render().contains("tidder_save_errors_total{kind=\"http_404\"} 1"));
+    }
+
+    #[test]
+    fn domains_in_flight_series() {
+        set_domains_in_flight("example.com", 2);
+        assert!(render().contains("tidder_domains_in_flight{host=\"example.com\"} 2"));
+
+        set_domains_in_flight("example.com", 0);
+        assert!(!render().contains("host=\"example.com\""));
+    }
+}
@@ -0,0 +1,171 @@
+use super::*;
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use subtle::ConstantTimeEq;
+
+/// How old rules stored in Postgres can get before a process loads them again
+const RELOAD_SECS: u64 = 30;
+
+/// A value loaded from Postgres that's shared by a whole process and periodically reloaded,
+/// so that changes made from the admin area reach every binary without a restart
+pub struct Reloaded<T> {
+    loaded: RwLock<Option<(Instant, Arc<T>)>>,
+    /// Held by the one task reloading the value
+    reloading: tokio::sync::Mutex<()>,
+}
+
+impl<T> Default for Reloaded<T> {
+    fn default() -> Self {
+        Self {
+            loaded: RwLock::new(None),
+            reloading: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+impl<T> Reloaded<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn current(&self) -> Option<(Instant, Arc<T>)> {
+        self.loaded
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|(at, value)| (*at, value.clone()))
+    }
+
+    fn fresh(&self) -> Option<Arc<T>> {
+        self.current()
+            .filter(|(at, _)| at.elapsed() < Duration::from_secs(RELOAD_SECS))
+            .map(|(_, value)| value)
+    }
+
+    /// Only one task reloads at a time, while the others keep serving the old value. If
+    /// reloading fails, the last good value is served too.
+    pub async fn get<F, Fut>(&self, load: F) -> Result<Arc<T>, UserError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, UserError>>,
+    {
+        if let Some(value) = self.fresh() {
+            return Ok(value);
+        }
+
+        let _reloading = match (self.reloading.try_lock(), self.current()) {
+            (Ok(reloading), _) => reloading,
+            (Err(_), Some((_, value))) => return Ok(value),
+            // Nothing's loaded yet, so wait for the first load
+            (Err(_), None) => self.reloading.lock().await,
+        };
+
+        // Another task may have reloaded it while this one waited
+        if let Some(value) = self.fresh() {
+            return Ok(value);
+        }
+
+        let current = self.current();
+
+        match load().await {
+            Ok(value) => {
+                let value = Arc::new(value);
+                *self.loaded.write().unwrap() = Some((Instant::now(), value.clone()));
+                Ok(value)
+            }
+            Err(ue) => match current {
+                Some((_, value)) => {
+                    warn!("failed to reload, keeping the old value: {}", ue.error);
+                    Ok(value)
+                }
+                None => Err(ue),
+            },
+        }
+    }
+}
+
+/// The configured ban rules together with those added from the admin area
+pub struct BanList {
+    rules: Vec<Banned>,
+}
+
+impl BanList {
+    pub async fn load() -> Result<Self, UserError> {
+        let rows = PG_POOL
+            .get()
+            .await?
+            .query("SELECT id, rule FROM banned_rules", &[])
+            .await?;
+
+        let rules = rows
+            .iter()
+            .filter_map(|row| match ron::from_str(row.get("rule")) {
+                Ok(rule) => Some(rule),
+                Err(e) => {
+                    warn!(
+                        "skipping invalid ban rule {}: {}",
+                        row.get::<_, i64>("id"),
+                        e
+                    );
+                    None
+                }
+            })
+            .collect();
+
+        Ok(Self { rules })
+    }
+
+    /// The first rule that bans a URL
+    pub fn find(&self, url: &str) -> Option<&Banned> {
+        CONFIG
+            .banned
+            .iter()
+            .chain(self.rules.iter())
+            .find(|banned| banned.matches(url))
+    }
+}
+
+static BAN_LIST: Lazy<Reloaded<BanList>> = Lazy::new(Reloaded::new);
+
+pub async fn ban_list() -> Result<Arc<BanList>, UserError> {
+    BAN_LIST.get(BanList::load).await
+}
+
+/// Checks an admin's password against the digest in `secrets.toml`, in constant time so the
+/// time taken doesn't depend on how much of it was right
+pub fn is_admin(name: &str, password: &str) -> bool {
+    SECRETS
+        .admins
+        .get(name)
+        .and_then(|expected| hex::decode(expected).ok())
+        .is_some_and(|expected| {
+            Sha256::digest(password.as_bytes())
+                .as_slice()
+                .ct_eq(&expected)
+                .into()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn reloads_once_at_a_time() {
+        let reloaded = Reloaded::new();
+        let loads = AtomicUsize::new(0);
+        let load = || async {
+            loads.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(1)
+        };
+
+        let (first, second) = tokio::join!(reloaded.get(load), reloaded.get(load));
+
+        assert_eq!(*first.unwrap(), 1);
+        assert_eq!(*second.unwrap(), 1);
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+    }
+}
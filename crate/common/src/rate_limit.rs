@@ -0,0 +1,189 @@
+use super::*;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often buckets that have refilled completely are forgotten
+const PRUNE_SECS: u64 = 60;
+
+struct Bucket {
+    limits: config::BucketLimits,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant) -> f64 {
+        let per_sec = self.limits.per_minute as f64 / 60.0;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * per_sec).min(self.limits.capacity as f64)
+    }
+
+    fn is_full(&self, now: Instant) -> bool {
+        self.refilled(now) >= self.limits.capacity as f64
+    }
+}
+
+/// Why tokens couldn't be taken from a bucket
+#[derive(Debug, PartialEq, Eq)]
+pub enum Shortfall {
+    /// The bucket will have enough tokens after this long
+    Wait(Duration),
+    /// The cost is more than the bucket's capacity, so it never will
+    OverCapacity(u32),
+}
+
+/// Token buckets, one per key, that each hold up to `capacity` tokens
+/// and refill at `per_minute` tokens a minute
+pub struct RateLimiter<K> {
+    limits: config::BucketLimits,
+    state: Mutex<(Instant, HashMap<K, Bucket>)>,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(limits: config::BucketLimits) -> Self {
+        Self {
+            limits,
+            state: Mutex::new((Instant::now(), HashMap::new())),
+        }
+    }
+
+    /// Takes `cost` tokens from `key`'s bucket, or returns why it can't yet
+    pub fn take(&self, key: K, cost: u32) -> Result<(), Shortfall> {
+        self.take_at(key, cost, self.limits, Instant::now())
+    }
+
+    /// Like `take`, but for a key with its own limits
+    pub fn take_with_limits(
+        &self,
+        key: K,
+        cost: u32,
+        limits: config::BucketLimits,
+    ) -> Result<(), Shortfall> {
+        self.take_at(key, cost, limits, Instant::now())
+    }
+
+    /// Gives back `cost` tokens taken for something that was then refused anyway
+    pub fn give_back(&self, key: K, cost: u32) {
+        self.give_back_at(key, cost, Instant::now())
+    }
+
+    fn give_back_at(&self, key: K, cost: u32, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(bucket) = state.1.get_mut(&key) {
+            bucket.tokens = (bucket.refilled(now) + cost as f64).min(bucket.limits.capacity as f64);
+            bucket.updated = now;
+        }
+    }
+
+    fn take_at(
+        &self,
+        key: K,
+        cost: u32,
+        limits: config::BucketLimits,
+        now: Instant,
+    ) -> Result<(), Shortfall> {
+        if cost > limits.capacity {
+            return Err(Shortfall::OverCapacity(limits.capacity));
+        }
+        let cost = cost as f64;
+
+        let mut state = self.state.lock().unwrap();
+        let (pruned, buckets) = &mut *state;
+
+        if now.saturating_duration_since(*pruned) >= Duration::from_secs(PRUNE_SECS) {
+            buckets.retain(|_, bucket| !bucket.is_full(now));
+            *pruned = now;
+        }
+
+        // A key's limits may have changed since its bucket was filled
+        let tokens = match buckets.get(&key) {
+            Some(bucket) => bucket.refilled(now).min(limits.capacity as f64),
+            None => limits.capacity as f64,
+        };
+
+        if tokens >= cost {
+            buckets.insert(
+                key,
+                Bucket {
+                    limits,
+                    tokens: tokens - cost,
+                    updated: now,
+                },
+            );
+            Ok(())
+        } else if limits.per_minute == 0 {
+            // A bucket that never refills won't ever have enough
+            Err(Shortfall::OverCapacity(limits.capacity))
+        } else {
+            let per_sec = limits.per_minute as f64 / 60.0;
+            Err(Shortfall::Wait(Duration::from_secs_f64(
+                (cost - tokens) / per_sec,
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take() {
+        let limits = config::BucketLimits {
+            capacity: 3,
+            per_minute: 60,
+        };
+        let limiter = RateLimiter::new(limits);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.take_at("a", 1, limits, start).is_ok());
+        }
+        assert_eq!(
+            limiter.take_at("a", 1, limits, start),
+            Err(Shortfall::Wait(Duration::from_secs(1)))
+        );
+        assert!(limiter.take_at("b", 1, limits, start).is_ok());
+
+        // Given back, but never past the capacity
+        limiter.give_back_at("b", 5, start);
+        assert!(limiter.take_at("b", 3, limits, start).is_ok());
+        assert!(limiter.take_at("b", 1, limits, start).is_err());
+
+        let later = start + Duration::from_secs(2);
+        assert!(limiter.take_at("a", 2, limits, later).is_ok());
+        assert!(limiter.take_at("a", 1, limits, later).is_err());
+
+        // Too costly to ever fit, so refused without taking anything
+        let much_later = later + Duration::from_secs(PRUNE_SECS);
+        assert_eq!(
+            limiter.take_at("a", 10, limits, much_later),
+            Err(Shortfall::OverCapacity(3))
+        );
+        assert!(limiter.take_at("a", 3, limits, much_later).is_ok());
+
+        // A bucket that never refills
+        let stuck = config::BucketLimits {
+            capacity: 1,
+            per_minute: 0,
+        };
+        assert!(limiter.take_at("d", 1, stuck, start).is_ok());
+        assert_eq!(
+            limiter.take_at("d", 1, stuck, start),
+            Err(Shortfall::OverCapacity(1))
+        );
+
+        // Keys with their own limits
+        let generous = config::BucketLimits {
+            capacity: 100,
+            per_minute: 600,
+        };
+        assert!(limiter.take_at("c", 100, generous, start).is_ok());
+        assert_eq!(
+            limiter.take_at("c", 10, generous, start),
+            Err(Shortfall::Wait(Duration::from_secs(1)))
+        );
+    }
+}
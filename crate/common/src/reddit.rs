@@ -0,0 +1,150 @@
+use super::*;
+
+const INFO_URL: &str = "https://api.reddit.com/api/info/";
+
+/// Finds the base-36 ID of the post a Reddit comments link, short link, or `t3_` fullname refers to.
+/// Comments links may leave out the host, like the permalinks stored with posts.
+pub fn reddit_post_id(link: &str) -> Option<String> {
+    static FULLNAME_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(?i)t3_([[:alnum:]]+)$").unwrap());
+    static COMMENTS_RE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(
+            r"^(?i)(?:(?:https?://)?(?:[a-z0-9-]+\.)?reddit\.com)?/(?:(?:r|u|user)/[^/?#]+/)?(?:comments|gallery)/([[:alnum:]]+)(?:$|[/?#])",
+        )
+        .unwrap()
+    });
+    static SHORT_RE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"^(?i)(?:https?://)?redd\.it/([[:alnum:]]+)/?(?:$|[?#])").unwrap()
+    });
+
+    let link = link.trim();
+
+    FULLNAME_RE
+        .captures(link)
+        .or_else(|| COMMENTS_RE.captures(link))
+        .or_else(|| SHORT_RE.captures(link))
+        .and_then(|caps| caps.get(1))
+        .map(|id| id.as_str().to_lowercase())
+}
+
+/// The path of a Reddit comments link, in the form the permalinks stored with posts take
+pub fn reddit_permalink(link: &str) -> Option<String> {
+    static PERMALINK_RE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(
+            r"^(?i)(?:(?:https?://)?(?:[a-z0-9-]+\.)?reddit\.com)?(/(?:r|u|user)/[^/?#]+/comments/[[:alnum:]]+/[^?#]*)",
+        )
+        .unwrap()
+    });
+
+    let path = PERMALINK_RE.captures(link.trim())?.get(1)?.as_str();
+
+    Some(if path.ends_with('/') {
+        path.to_string()
+    } else {
+        format!("{}/", path)
+    })
+}
+
+/// Fetches a post from Reddit's info API
+pub async fn get_submission(id: &str) -> Result<Option<Submission>, UserError> {
+    #[derive(Deserialize)]
+    struct Child {
+        data: Submission,
+    }
+
+    #[derive(Deserialize)]
+    struct Data {
+        children: Vec<Child>,
+    }
+
+    #[derive(Deserialize)]
+    struct Info {
+        data: Data,
+    }
+
+    let info = REQW_CLIENT
+        .get(INFO_URL)
+        .query(&[("id", format!("t3_{}", id).as_str()), ("raw_json", "1")])
+        .send()
+        .map_err(map_ue!("couldn't reach Reddit API"))
+        .await?
+        .error_for_status()
+        .map_err(error_for_status_ue)?
+        .json::<Info>()
+        .map_err(map_ue!("Reddit API returned problematic JSON"))
+        .await?;
+
+    info.data
+        .children
+        .into_iter()
+        .next()
+        .map(|child| child.data.finalize())
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn post_ids() {
+        assert_eq!(reddit_post_id("t3_abc123"), Some("abc123".to_string()));
+        assert_eq!(
+            reddit_post_id("https://www.reddit.com/r/pics/comments/abc123/some_title/"),
+            Some("abc123".to_string())
+        );
+        assert_eq!(
+            reddit_post_id("https://old.reddit.com/r/pics/comments/abc123"),
+            Some("abc123".to_string())
+        );
+        assert_eq!(
+            reddit_post_id("reddit.com/comments/ABC123/"),
+            Some("abc123".to_string())
+        );
+        assert_eq!(
+            reddit_post_id("https://redd.it/abc123"),
+            Some("abc123".to_string())
+        );
+        assert_eq!(
+            reddit_post_id("https://www.reddit.com/gallery/abc123"),
+            Some("abc123".to_string())
+        );
+        assert_eq!(
+            reddit_post_id("/r/pics/comments/abc123/some_title/"),
+            Some("abc123".to_string())
+        );
+        assert_eq!(
+            reddit_post_id("https://www.reddit.com/user/someone/comments/abc123/some_title/"),
+            Some("abc123".to_string())
+        );
+    }
+
+    #[test]
+    fn permalinks() {
+        assert_eq!(
+            reddit_permalink("https://www.reddit.com/r/pics/comments/abc123/some_title/?context=3"),
+            Some("/r/pics/comments/abc123/some_title/".to_string())
+        );
+        assert_eq!(
+            reddit_permalink("/r/pics/comments/abc123/some_title"),
+            Some("/r/pics/comments/abc123/some_title/".to_string())
+        );
+        assert_eq!(reddit_permalink("t3_abc123"), None);
+        assert_eq!(reddit_permalink("https://redd.it/abc123"), None);
+        assert_eq!(
+            reddit_permalink("https://notreddit.com/r/pics/comments/abc123/some_title/"),
+            None
+        );
+    }
+
+    #[test]
+    fn not_post_ids() {
+        assert_eq!(reddit_post_id("https://i.redd.it/abc123.jpg"), None);
+        assert_eq!(reddit_post_id("https://www.reddit.com/r/pics/"), None);
+        assert_eq!(
+            reddit_post_id("https://notreddit.com/r/pics/comments/abc123/"),
+            None
+        );
+        assert_eq!(reddit_post_id("https://imgur.com/abc123"), None);
+        assert_eq!(reddit_post_id("t3_"), None);
+    }
+}
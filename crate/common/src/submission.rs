@@ -107,9 +107,10 @@ impl Submission {
 
         let rows = match image_id {
             Ok(image_id) => {
+                // The rankings' counts are bumped in the same statement so they can't drift
                 let stmt = client
                     .prepare(
-                        "INSERT INTO posts \
+                        "WITH post AS (INSERT INTO posts \
                          (reddit_id, link, permalink, author, \
                          created_utc, score, subreddit, title, nsfw, \
                          spoiler, image_id, is_video, preview, reddit_id_int, \
@@ -117,7 +118,21 @@ impl Submission {
                          crosspost_parent) \
                          VALUES ($1, $2, $3, $4, $5, $6, $7, \
                          $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18) \
-                         ON CONFLICT DO NOTHING RETURNING id",
+                         ON CONFLICT DO NOTHING RETURNING id, created_utc, subreddit, image_id), \
+                         counted AS (INSERT INTO image_counts (day, subreddit, hash, num) \
+                         SELECT post.created_utc::date, LOWER(post.subreddit), images.hash, 1 \
+                         FROM post INNER JOIN images ON images.id = post.image_id \
+                         WHERE NOT EXISTS \
+                         (SELECT FROM takedowns WHERE takedowns.image_id = images.id) \
+                         ON CONFLICT (day, subreddit, hash) \
+                         DO UPDATE SET num = image_counts.num + 1), \
+                         totalled AS (INSERT INTO image_totals (hash, num) \
+                         SELECT images.hash, 1 \
+                         FROM post INNER JOIN images ON images.id = post.image_id \
+                         WHERE NOT EXISTS \
+                         (SELECT FROM takedowns WHERE takedowns.image_id = images.id) \
+                         ON CONFLICT (hash) DO UPDATE SET num = image_totals.num + 1) \
+                         SELECT id FROM post",
                     )
                     .await?;
                 client
@@ -160,7 +175,7 @@ impl Submission {
                          ON CONFLICT DO NOTHING RETURNING id",
                     )
                     .await?;
-                client
+                let rows = client
                     .query(
                         &stmt,
                         &[
@@ -184,7 +199,13 @@ impl Submission {
                             &self.preview,
                         ],
                     )
-                    .await?
+                    .await?;
+
+                if let (false, Some(save_error)) = (rows.is_empty(), &save_error) {
+                    metrics::count_save_error(save_error);
+                }
+
+                rows
             }
         };
 
@@ -0,0 +1,203 @@
+use super::*;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+/// Holds the HMAC-SHA256 of a webhook's body, keyed with its watch's secret
+pub const SIGNATURE_HEADER: &str = "X-Tidder-Signature";
+
+/// A random hex string for a watch's feed token or webhook secret
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[derive(Serialize)]
+struct HitImage<'a> {
+    id: i64,
+    link: &'a str,
+    hash: String,
+}
+
+#[derive(Serialize)]
+struct HitPost<'a> {
+    id: &'a str,
+    permalink: String,
+    author: &'a str,
+    subreddit: &'a str,
+    title: &'a str,
+    created_utc: NaiveDateTime,
+}
+
+#[derive(Serialize)]
+struct Hit<'a> {
+    watch_id: i64,
+    hit_id: i64,
+    distance: i64,
+    image: HitImage<'a>,
+    post: HitPost<'a>,
+}
+
+async fn deliver(hit_id: i64, webhook: String, secret: String, body: Vec<u8>) {
+    // Webhooks are chosen by whoever created the watch
+    let res = FetchPolicy::Public
+        .client()
+        .post(&webhook)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, sign(&secret, &body))
+        .body(body)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status());
+
+    let delivery_error = match res {
+        Ok(_) => None,
+        Err(e) => {
+            warn!(
+                "failed to deliver watch hit {} to {}: {}",
+                hit_id, webhook, e
+            );
+            Some(e.to_string())
+        }
+    };
+
+    let updated = async {
+        PG_POOL
+            .get()
+            .await?
+            .execute(
+                "UPDATE watch_hits SET delivered_on = $2, delivery_error = $3 WHERE id = $1",
+                &[
+                    &hit_id,
+                    &chrono::offset::Utc::now().naive_utc(),
+                    &delivery_error,
+                ],
+            )
+            .await?;
+
+        Ok::<_, UserError>(())
+    }
+    .await;
+
+    if let Err(e) = updated {
+        warn!(
+            "failed to record delivery of watch hit {}: {}",
+            hit_id, e.error
+        );
+    }
+}
+
+/// Records a hit for each active watch a newly stored image matches, sending its webhook in the
+/// background. Images that were already stored have already been checked.
+pub async fn check_watches(saved: &HashSaved, post: &Submission) -> Result<(), UserError> {
+    if !saved.stored {
+        return Ok(());
+    }
+
+    let client = PG_POOL.get().await?;
+
+    let watches = client
+        .query(
+            "SELECT id, webhook, secret, hash <-> $1 as hit_distance FROM watches \
+             WHERE active AND hash <@ ($1, $2) AND hash <-> $1 <= distance \
+             AND (subreddits IS NULL OR LOWER($3) = ANY(subreddits))",
+            &[
+                &saved.hashes.dhash,
+                &(CONFIG.max_distance as i64),
+                &post.subreddit,
+            ],
+        )
+        .await?;
+
+    if watches.is_empty() {
+        return Ok(());
+    }
+
+    let link: String = match client
+        .query_opt(
+            "SELECT link FROM images WHERE id = $1 \
+             AND NOT EXISTS (SELECT FROM takedowns WHERE takedowns.image_id = images.id)",
+            &[&saved.id],
+        )
+        .await?
+    {
+        Some(image) => image.get("link"),
+        None => return Ok(()),
+    };
+
+    for watch in watches {
+        let watch_id: i64 = watch.get("id");
+        let distance: i64 = watch.get("hit_distance");
+
+        let hit_id: i64 = client
+            .query_one(
+                "INSERT INTO watch_hits (watch_id, image_id, reddit_id_int, distance, created) \
+                 VALUES ($1, $2, $3, $4, $5) RETURNING id",
+                &[
+                    &watch_id,
+                    &saved.id,
+                    &post.id_int,
+                    &distance,
+                    &chrono::offset::Utc::now().naive_utc(),
+                ],
+            )
+            .await?
+            .get("id");
+
+        info!("image {} hit watch {}", saved.id, watch_id);
+
+        if let Some(webhook) = watch.get::<_, Option<String>>("webhook") {
+            let body = serde_json::to_vec(&Hit {
+                watch_id,
+                hit_id,
+                distance,
+                image: HitImage {
+                    id: saved.id,
+                    link: &link,
+                    hash: saved.hashes.dhash.to_string(),
+                },
+                post: HitPost {
+                    id: &post.id,
+                    permalink: format!("https://reddit.com{}", post.permalink),
+                    author: &post.author,
+                    subreddit: &post.subreddit,
+                    title: &post.title,
+                    created_utc: post.created_utc,
+                },
+            })?;
+
+            tokio::spawn(deliver(hit_id, webhook, watch.get("secret"), body));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature() {
+        // From RFC 4231's second test case
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn tokens() {
+        let token = random_token();
+        assert_eq!(token.len(), 64);
+        assert!(token.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_ne!(token, random_token());
+    }
+}
@@ -15,15 +15,24 @@ const BASE_GET_URL: &str = "https://api.reddit.com/api/info/?id=";
 const ERROR_WAIT: Duration = Duration::from_secs(5);
 
 async fn ingest_post(post: Submission) -> bool {
-    let post_url_res = post.choose_url();
+    let post_url_res = (|| async {
+        let post_url = post.choose_url()?;
+
+        if let Some(banned) = ban_list().await?.find(post_url.as_str()) {
+            return Err(ue_save!("banned", banned.save_error()));
+        }
+
+        Ok(post_url)
+    })()
+    .await;
 
     let save_res = match post_url_res {
-        Ok(post_url) => save_hash(post_url.as_str(), HashDest::Images).await,
+        Ok(post_url) => save_hash(post_url.as_str(), HashDest::Images, FetchPolicy::Trusted).await,
         Err(e) => Err(e),
     };
 
-    let image_id = match save_res {
-        Ok(hash_gotten) => Ok(hash_gotten.id),
+    let saved = match save_res {
+        Ok(hash_gotten) => Ok(hash_gotten),
         Err(ue) => match ue.source {
             Source::Internal => {
                 eprintln!(
@@ -78,15 +87,20 @@ async fn ingest_post(post: Submission) -> bool {
         },
     };
 
-    let good = image_id.is_ok();
-
-    match post.save(image_id).await {
+    match post
+        .save(saved.as_ref().map(|saved| saved.id).map_err(Clone::clone))
+        .await
+    {
         Ok(already_have) => {
-            if good {
+            if let Ok(saved) = &saved {
                 if already_have {
                     info!("already have");
                 } else {
                     info!("successfully saved");
+
+                    if let Err(e) = watch::check_watches(saved, &post).await {
+                        warn!("failed to check watches: {}", e.error);
+                    }
                 }
             }
             already_have
@@ -147,6 +161,8 @@ async fn get_100(
 async fn main() -> Result<(), UserError> {
     tracing_subscriber::fmt::init();
 
+    metrics::listen("direct");
+
     let start_id = i64::from_str_radix(&std::env::args().nth(1).unwrap(), 36)?;
 
     let mut getter_fut = Box::pin(tokio::spawn(get_100(None, start_id..start_id + 100)));
@@ -56,12 +56,8 @@ async fn ingest_post(
             return Err(ue_save!("blacklisted", "blacklisted"));
         }
 
-        if CONFIG
-            .banned
-            .iter()
-            .any(|banned| banned.matches(post_url.as_str()))
-        {
-            return Err(ue_save!("banned", "banned"));
+        if let Some(banned) = ban_list().await?.find(post_url.as_str()) {
+            return Err(ue_save!("banned", banned.save_error()));
         }
 
         Ok(post_url)
@@ -91,7 +87,9 @@ async fn ingest_post(
                     .unwrap_or(true);
 
                 if ready {
-                    *(domains_in_flight.entry(host.to_owned()).or_insert(0)) += 1;
+                    let mut in_flight = domains_in_flight.entry(host.to_owned()).or_insert(0);
+                    *in_flight += 1;
+                    metrics::set_domains_in_flight(host, *in_flight);
 
                     Poll::Ready(host.to_owned())
                 } else {
@@ -105,22 +103,27 @@ async fn ingest_post(
                 info!("Starting to save");
             }
 
-            let res = save_hash(post_url.as_str(), HashDest::Images).await;
+            let res = save_hash(post_url.as_str(), HashDest::Images, FetchPolicy::Trusted).await;
 
-            *domains_in_flight.get_mut(host).unwrap() -= 1;
+            // Updated while the entry is held, so the series can't be dropped after another
+            // request to the host starts
+            let mut in_flight = domains_in_flight.get_mut(host).unwrap();
+            *in_flight -= 1;
+            metrics::set_domains_in_flight(host, *in_flight);
+            drop(in_flight);
 
             res
         }
         Err(e) => Err(e),
     };
 
-    let image_id = match save_res {
+    let saved = match save_res {
         Ok(hash_gotten) => {
             if verbose {
                 info!("successfully hashed");
             }
 
-            Ok(hash_gotten.id)
+            Ok(hash_gotten)
         }
         Err(ue) => match ue.source {
             Source::Internal => {
@@ -151,6 +154,7 @@ async fn ingest_post(
                                 if let Some(host) = url.host_str() {
                                     if !CONFIG.no_blacklist.iter().any(|n| host.ends_with(n)) {
                                         blacklist.insert(host.to_string(), ());
+                                        metrics::BLACKLIST_SIZE.set(blacklist.len() as i64);
                                     }
                                 }
                             }
@@ -186,10 +190,19 @@ async fn ingest_post(
         },
     };
 
-    match post.save(image_id).await {
+    match post
+        .save(saved.as_ref().map(|saved| saved.id).map_err(Clone::clone))
+        .await
+    {
         Ok(_) => {
             POST_COUNT.fetch_add(1, Ordering::SeqCst);
             info!("successfully saved");
+
+            if let Ok(saved) = &saved {
+                if let Err(e) = watch::check_watches(saved, &post).await {
+                    warn!("failed to check watches: {}", e.error);
+                }
+            }
         }
         Err(e) => {
             error!("post \n{:#?} \nfailed to save:\n{:?}", post, e);
@@ -272,6 +285,7 @@ async fn ingest_json<R: Read + 'static>(
             .unwrap();
 
             POSTS_PER_MINUTE.store(current_speed, Ordering::SeqCst);
+            metrics::POSTS_PER_MINUTE.set(current_speed as i64);
 
             let pretty_config = ron::ser::PrettyConfig::new();
 
@@ -353,6 +367,8 @@ async fn main() -> Result<(), UserError> {
 
     let args = Cli::parse();
 
+    metrics::listen("ingest");
+
     let verbose = args.verbose;
     let path = args.path;
 
@@ -1,4 +1,4 @@
-use clap::clap_app;
+use clap::{Arg, ArgAction, Command};
 use common::*;
 use futures::prelude::*;
 use hash_trie::HashTrie;
@@ -6,6 +6,7 @@ use reqwest::{header::USER_AGENT, Client};
 use serde::Deserialize;
 use serde_json::Value;
 use std::io::{Read, Write};
+use tokio_postgres::types::ToSql;
 
 async fn post(ids: impl Iterator<Item = &str>) -> Result<(), UserError> {
     const REDDIT_USER_AGENT: &str = concat!(
@@ -108,7 +109,7 @@ async fn save(id: &str) -> Result<(), UserError> {
             Submission::deserialize(&resp.json::<Value>().await?["data"]["children"][0]["data"])?
                 .finalize()?;
 
-        let hash_saved = save_hash(&post.url, HashDest::Images).await?;
+        let hash_saved = save_hash(&post.url, HashDest::Images, FetchPolicy::Trusted).await?;
 
         if post.save(Ok(hash_saved.id)).await? {
             println!("already have");
@@ -126,7 +127,9 @@ async fn save(id: &str) -> Result<(), UserError> {
 async fn hash(links: &[&str]) -> Result<(), UserError> {
     futures::stream::iter(links.iter())
         .fold(None, move |last, arg| async move {
-            let HashGotten { hash, end_link, .. } = match get_hash(&arg).await {
+            let HashGotten {
+                hashes, end_link, ..
+            } = match get_hash(arg, FetchPolicy::Trusted, true).await {
                 Ok(res) => res,
                 Err(e) => {
                     warn!("{} failed: {:?}", arg, e);
@@ -134,7 +137,8 @@ async fn hash(links: &[&str]) -> Result<(), UserError> {
                 }
             };
 
-            let mut out = format!("{}: {}", end_link, hash);
+            let hash = hashes.dhash;
+            let mut out = format!("{}: {}", end_link, hashes);
             if let Some(last) = last {
                 out = format!("{} ({})", out, distance(hash, last));
             }
@@ -147,31 +151,96 @@ async fn hash(links: &[&str]) -> Result<(), UserError> {
     Ok(())
 }
 
-async fn search(link: &str, distance: Option<i64>) -> Result<(), UserError> {
+async fn search(link: &str, distance: Option<i64>, transforms: bool) -> Result<(), UserError> {
     const DEFAULT_DISTANCE: i64 = 2;
 
     let distance = distance.unwrap_or(DEFAULT_DISTANCE);
 
-    let hash = get_hash(link).await?.hash;
+    let hashes = if transforms {
+        refetch_hash(link, FetchPolicy::Trusted, true, false).await?
+    } else {
+        get_hash(link, FetchPolicy::Trusted, false).await?.hashes
+    };
+
+    // Each hash to search for, with the transform of the image it's the hash of
+    let names = hashes
+        .transforms
+        .iter()
+        .map(|(transform, _)| transform.name())
+        .collect::<Vec<_>>();
+    let mut args: Vec<&(dyn ToSql + Sync)> = vec![
+        &distance,
+        &WIDE_SCALE,
+        &hashes.dhash,
+        &hashes.wide,
+        &hashes.trimmed,
+    ];
+    let mut query_hashes =
+        vec!["(NULL::varchar, $3::bigint, $4::bit(256), $5::bigint)".to_string()];
+    if transforms {
+        for ((_, hash), name) in hashes.transforms.iter().zip(&names) {
+            args.push(name);
+            args.push(hash);
+            query_hashes.push(format!(
+                "(${}, ${}, NULL, NULL)",
+                args.len() - 1,
+                args.len()
+            ));
+        }
+    }
+
+    // Candidates come from the 64-bit hash, or the one with borders trimmed if it's closer,
+    // then are ranked and filtered by the 256-bit one. That's of the untrimmed image, so
+    // doesn't apply to those found by trimming.
+    let raw_distance = "hash <-> query_hash";
+    let trimmed_distance = "images.trimmed_hash <-> query_trimmed_hash";
+    let trimmed = format!("COALESCE({} < {}, false)", trimmed_distance, raw_distance);
+    let closest_distance = format!("LEAST({}, {})", raw_distance, trimmed_distance);
+    let wide_distance = format!(
+        "CASE WHEN {} THEN NULL ELSE bit_count(images.wide_hash # query_wide_hash) END",
+        trimmed
+    );
 
+    // A post found through several hashes is only shown for its closest
     let found = PG_POOL
         .get()
         .await?
         .query(
-            "SELECT hash <-> $1 as distance, images.link, permalink, \
-             score, author, created_utc, subreddit, title \
-             FROM posts INNER JOIN images \
-             ON hash <@ ($1, $2) \
-             AND image_id = images.id \
-             ORDER BY distance ASC, created_utc ASC",
-            &[&hash, &distance],
+            format!(
+                "SELECT * FROM (\
+                 SELECT DISTINCT ON (posts.id) transform, {trimmed} as trimmed, \
+                 {distance} as distance, {wide_distance} as wide_distance, \
+                 COALESCE({wide_distance}, $2 * {distance}) as rank, \
+                 images.link, permalink, score, author, created_utc, subreddit, title \
+                 FROM (VALUES {query_hashes}) \
+                 AS query_hashes (transform, query_hash, query_wide_hash, query_trimmed_hash) \
+                 INNER JOIN images ON (hash <@ (query_hash, $1) \
+                 OR trimmed_hash <@ (query_trimmed_hash, $1)) \
+                 INNER JOIN posts ON image_id = images.id \
+                 WHERE COALESCE({wide_distance} <= $1 * $2, true) \
+                 ORDER BY posts.id, rank, distance\
+                 ) matches ORDER BY rank ASC, distance ASC, created_utc ASC",
+                trimmed = trimmed,
+                distance = closest_distance,
+                wide_distance = wide_distance,
+                query_hashes = query_hashes.join(", ")
+            )
+            .as_str(),
+            &args,
         )
         .await?;
 
     for row in found {
         println!(
-            "{} | {} | {} | {} | {} | /r/{} | {} | {}",
+            "{} ({}) | {} | {} | {} | {} | {} | /r/{} | {} | {}",
             row.get::<_, i64>("distance"),
+            row.get::<_, Option<i64>>("wide_distance")
+                .map_or_else(|| "-".to_string(), |d| d.to_string()),
+            match (row.get::<_, Option<&str>>("transform"), row.get("trimmed")) {
+                (Some(transform), _) => transform,
+                (None, true) => "trimmed",
+                (None, false) => "-",
+            },
             row.get::<_, chrono::NaiveDateTime>("created_utc"),
             row.get::<_, i64>("score"),
             row.get::<_, &str>("link"),
@@ -185,28 +254,233 @@ async fn search(link: &str, distance: Option<i64>) -> Result<(), UserError> {
     Ok(())
 }
 
+/// Rebuilds the rankings' counts from every saved post that wasn't taken down
 async fn rank() -> Result<(), UserError> {
+    let mut client = PG_POOL.get().await?;
+    let trans = client.transaction().await?;
+
+    trans
+        .execute("TRUNCATE image_counts, image_totals", &[])
+        .await?;
+
+    let counted = trans
+        .execute(
+            "INSERT INTO image_counts (day, subreddit, hash, num) \
+             SELECT created_utc::date, LOWER(subreddit), hash, COUNT(*) \
+             FROM posts INNER JOIN images ON image_id = images.id \
+             WHERE NOT EXISTS (SELECT FROM takedowns WHERE takedowns.post_id = posts.id) \
+             AND NOT EXISTS (SELECT FROM takedowns WHERE takedowns.image_id = images.id) \
+             GROUP BY 1, 2, 3",
+            &[],
+        )
+        .await?;
+    trans
+        .execute(
+            "INSERT INTO image_totals (hash, num) \
+             SELECT hash, SUM(num) FROM image_counts GROUP BY hash",
+            &[],
+        )
+        .await?;
+
+    trans.commit().await?;
+
+    println!("Counted {} image-days", counted);
+
+    Ok(())
+}
+
+/// Unlinks saved posts from images matching the denylist, then deletes those images
+async fn purge_denied(dry_run: bool) -> Result<(), UserError> {
+    let denylist = denylist().await?;
+
+    let mut client = PG_POOL.get().await?;
+    let trans = client.transaction().await?;
+
+    let mut image_ids = Vec::<i64>::new();
+    let mut cached = 0;
+    for denied in denylist.entries() {
+        let distance = denied.distance as i64;
+
+        // Like when saving, an image is denied by its trimmed hash or any of its frames too
+        image_ids.extend(
+            trans
+                .query(
+                    "SELECT id FROM images WHERE hash <@ ($1, $2) \
+                     UNION SELECT id FROM images WHERE trimmed_hash <@ ($1, $2) \
+                     UNION SELECT image_id FROM image_frames WHERE hash <@ ($1, $2)",
+                    &[&denied.hash, &distance],
+                )
+                .await?
+                .iter()
+                .map(|row| row.get::<_, i64>("id")),
+        );
+
+        cached += trans
+            .execute(
+                "DELETE FROM image_cache WHERE hash <@ ($1, $2) OR trimmed_hash <@ ($1, $2) \
+                 OR id IN (SELECT image_id FROM image_cache_frames WHERE hash <@ ($1, $2))",
+                &[&denied.hash, &distance],
+            )
+            .await?;
+    }
+    image_ids.sort_unstable();
+    image_ids.dedup();
+
+    let posts = trans
+        .execute(
+            "UPDATE posts SET image_id = NULL, save_error = 'hash_denied' \
+             WHERE image_id = ANY($1)",
+            &[&image_ids],
+        )
+        .await?;
+
+    // Images sharing a counted hash are copies of a denied one, so the whole count goes
+    trans
+        .execute(
+            "DELETE FROM image_counts \
+             WHERE hash IN (SELECT hash FROM images WHERE id = ANY($1))",
+            &[&image_ids],
+        )
+        .await?;
+    trans
+        .execute(
+            "DELETE FROM image_totals \
+             WHERE hash IN (SELECT hash FROM images WHERE id = ANY($1))",
+            &[&image_ids],
+        )
+        .await?;
+    trans
+        .execute(
+            "DELETE FROM watch_hits WHERE image_id = ANY($1)",
+            &[&image_ids],
+        )
+        .await?;
+    trans
+        .execute(
+            "DELETE FROM takedowns WHERE image_id = ANY($1)",
+            &[&image_ids],
+        )
+        .await?;
+    trans
+        .execute(
+            "DELETE FROM image_frames WHERE image_id = ANY($1)",
+            &[&image_ids],
+        )
+        .await?;
+    let images = trans
+        .execute("DELETE FROM images WHERE id = ANY($1)", &[&image_ids])
+        .await?;
+
+    if dry_run {
+        trans.rollback().await?;
+    } else {
+        trans.commit().await?;
+    }
+
+    println!(
+        "{} {} posts, {} images and {} cached images",
+        if dry_run { "Would purge" } else { "Purged" },
+        posts,
+        images,
+        cached
+    );
+
+    Ok(())
+}
+
+/// Issues an API key, printing it since only its digest is kept
+async fn issue_key(
+    name: &str,
+    capacity: Option<i64>,
+    per_minute: Option<i64>,
+    daily_quota: Option<i64>,
+    allowed_origins: Option<Vec<&str>>,
+) -> Result<(), UserError> {
+    let key = new_api_key();
+
+    let id: i64 = PG_POOL
+        .get()
+        .await?
+        .query_one(
+            "INSERT INTO api_keys \
+             (name, digest, capacity, per_minute, daily_quota, allowed_origins, created) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+            &[
+                &name,
+                &api_key_digest(&key),
+                &capacity,
+                &per_minute,
+                &daily_quota,
+                &allowed_origins,
+                &chrono::offset::Utc::now().naive_utc(),
+            ],
+        )
+        .await?
+        .get("id");
+
+    println!("Issued key {} to {}: {}", id, name, key);
+
+    Ok(())
+}
+
+async fn list_keys() -> Result<(), UserError> {
+    fn or_default(limit: Option<i64>) -> String {
+        limit.map_or_else(|| "default".to_string(), |limit| limit.to_string())
+    }
+
     let rows = PG_POOL
         .get()
         .await?
         .query(
-            "SELECT COUNT(*) AS num,
-             (SELECT link FROM images AS images2 WHERE images.hash <@ (images2.hash, 0) LIMIT 1) AS link
-             FROM images GROUP BY hash ORDER BY num DESC LIMIT 100", &[]).await?;
-
-    let commons = CommonImages {
-        as_of: chrono::offset::Utc::now(),
-        common_images: rows
-            .iter()
-            .map(|row| CommonImage {
-                num: row.get::<_, i64>("num") as u64,
-                link: row.get("link"),
-            })
-            .collect::<Vec<_>>(),
-    };
+            "SELECT id, name, capacity, per_minute, daily_quota, allowed_origins, \
+             created, revoked, \
+             COALESCE(SUM(searches) FILTER (WHERE day = $1), 0)::bigint AS today, \
+             COALESCE(SUM(searches), 0)::bigint AS total \
+             FROM api_keys LEFT JOIN api_key_usage ON key_id = id \
+             GROUP BY id ORDER BY id",
+            &[&chrono::offset::Utc::now().naive_utc().date()],
+        )
+        .await?;
 
-    std::fs::File::create(std::env::var("HOME")? + "/stats/top100.ron")?
-        .write_all(ron::ser::to_string_pretty(&commons, Default::default())?.as_bytes())?;
+    for row in rows {
+        println!(
+            "{} | {} | {} | {} | capacity {}, {} a minute, {} a day | origins {} | \
+             {} today, {} total",
+            row.get::<_, i64>("id"),
+            row.get::<_, &str>("name"),
+            row.get::<_, chrono::NaiveDateTime>("created"),
+            row.get::<_, Option<chrono::NaiveDateTime>>("revoked")
+                .map_or_else(|| "active".to_string(), |at| format!("revoked {}", at)),
+            or_default(row.get("capacity")),
+            or_default(row.get("per_minute")),
+            row.get::<_, Option<i64>>("daily_quota")
+                .map_or_else(|| "unlimited".to_string(), |quota| quota.to_string()),
+            row.get::<_, Option<Vec<String>>>("allowed_origins")
+                .map_or_else(|| "any".to_string(), |origins| origins.join(", ")),
+            row.get::<_, i64>("today"),
+            row.get::<_, i64>("total"),
+        );
+    }
+
+    Ok(())
+}
+
+/// Revokes an API key, keeping its usage. The site notices when it next reloads its keys.
+async fn revoke_key(id: i64) -> Result<(), UserError> {
+    let revoked = PG_POOL
+        .get()
+        .await?
+        .execute(
+            "UPDATE api_keys SET revoked = $2 WHERE id = $1 AND revoked IS NULL",
+            &[&id, &chrono::offset::Utc::now().naive_utc()],
+        )
+        .await?;
+
+    if revoked == 0 {
+        return Err(ue!(format!("No active key with ID {}", id)));
+    }
+
+    println!("Revoked key {}", id);
 
     Ok(())
 }
@@ -216,6 +490,7 @@ async fn trie_build(path: &str, id_path: &str) -> Result<(), UserError> {
         .read(true)
         .write(true)
         .create(true)
+        .truncate(false)
         .open(id_path)?;
 
     let mut old_id = String::new();
@@ -281,63 +556,186 @@ async fn trie_insert(path: &str, hashes: impl Iterator<Item = u64>) -> Result<()
 async fn main() -> Result<(), UserError> {
     setup_logging!();
 
-    let matches = clap_app!(op =>
-        (@subcommand hash =>
-         (@arg LINKS: +required ... "The links you wish to hash")
+    let matches = Command::new("op")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("hash").arg(
+                Arg::new("LINKS")
+                    .required(true)
+                    .num_args(1..)
+                    .help("The links you wish to hash"),
+            ),
         )
-        (@subcommand post =>
-         (@arg ID: +required ... "Reddit's IDs for the posts")
+        .subcommand(
+            Command::new("issue_key")
+                .arg(Arg::new("NAME").required(true).help("Who the key is for"))
+                .arg(
+                    Arg::new("capacity")
+                        .short('c')
+                        .long("capacity")
+                        .help("How many searches the key may burst"),
+                )
+                .arg(
+                    Arg::new("per_minute")
+                        .short('m')
+                        .long("per-minute")
+                        .help("How many searches a minute it may sustain"),
+                )
+                .arg(
+                    Arg::new("daily_quota")
+                        .short('q')
+                        .long("daily-quota")
+                        .help("How many searches it may make a day"),
+                )
+                .arg(
+                    Arg::new("origin")
+                        .short('o')
+                        .long("origin")
+                        .action(ArgAction::Append)
+                        .help("An origin browsers may use the key from; any if none are given"),
+                ),
         )
-        (@subcommand rank => )
-        (@subcommand save =>
-         (@arg ID: +required "Reddit's ID for the post you wish to save")
+        .subcommand(Command::new("list_keys"))
+        .subcommand(
+            Command::new("post").arg(
+                Arg::new("ID")
+                    .required(true)
+                    .num_args(1..)
+                    .help("Reddit's IDs for the posts"),
+            ),
         )
-        (@subcommand search =>
-         (@arg LINK: +required "The link to the image you wish to search for")
-         (@arg distance: -d --distance +takes_value "The max distance you'll accept")
+        .subcommand(
+            Command::new("purge_denied").arg(
+                Arg::new("dry_run")
+                    .short('n')
+                    .long("dry-run")
+                    .action(ArgAction::SetTrue)
+                    .help("Only count what would be purged"),
+            ),
         )
-        (@subcommand trie_build =>
-         (@arg PATH: +required "The path to save the trie to")
-         (@arg ID_PATH: +required "The path to save the last ID to")
+        .subcommand(Command::new("rank"))
+        .subcommand(
+            Command::new("revoke_key").arg(
+                Arg::new("ID")
+                    .required(true)
+                    .help("The ID of the key to revoke"),
+            ),
         )
-        (@subcommand trie_insert =>
-         (@arg PATH: +required "The path of the trie file")
-         (@arg HASHES: +required ... "The hashes you wish to save")
+        .subcommand(
+            Command::new("save").arg(
+                Arg::new("ID")
+                    .required(true)
+                    .help("Reddit's ID for the post you wish to save"),
+            ),
         )
-    )
-    .get_matches();
-
-    let (op_name, op_matches) = matches.subcommand();
-    let op_matches = op_matches.ok_or_else(|| ue!("No subcommand provided"))?;
+        .subcommand(
+            Command::new("search")
+                .arg(
+                    Arg::new("LINK")
+                        .required(true)
+                        .help("The link to the image you wish to search for"),
+                )
+                .arg(
+                    Arg::new("distance")
+                        .short('d')
+                        .long("distance")
+                        .help("The max distance you'll accept"),
+                )
+                .arg(
+                    Arg::new("transforms")
+                        .short('t')
+                        .long("transforms")
+                        .action(ArgAction::SetTrue)
+                        .help("Also search for the image flipped and rotated"),
+                ),
+        )
+        .subcommand(
+            Command::new("trie_build")
+                .arg(
+                    Arg::new("PATH")
+                        .required(true)
+                        .help("The path to save the trie to"),
+                )
+                .arg(
+                    Arg::new("ID_PATH")
+                        .required(true)
+                        .help("The path to save the last ID to"),
+                ),
+        )
+        .subcommand(
+            Command::new("trie_insert")
+                .arg(
+                    Arg::new("PATH")
+                        .required(true)
+                        .help("The path of the trie file"),
+                )
+                .arg(
+                    Arg::new("HASHES")
+                        .required(true)
+                        .num_args(1..)
+                        .help("The hashes you wish to save"),
+                ),
+        )
+        .get_matches();
+
+    let (op_name, op_matches) = matches
+        .subcommand()
+        .ok_or_else(|| ue!("No subcommand provided"))?;
+
+    let value_of = |name| op_matches.get_one::<String>(name).map(String::as_str);
+    let values_of = |name| {
+        op_matches
+            .get_many::<String>(name)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+    };
 
     match op_name {
-        "hash" => hash(&op_matches.values_of("LINKS").unwrap().collect::<Vec<_>>()).await,
-        "post" => post(op_matches.values_of("ID").unwrap()).await,
-        "rank" => rank().await,
-        "save" => save(op_matches.value_of("ID").unwrap()).await,
-        "search" => {
-            search(
-                op_matches.value_of("LINK").unwrap(),
-                op_matches
-                    .value_of("distance")
-                    .map(|d| d.parse())
-                    .transpose()?,
+        "hash" => hash(&values_of("LINKS").collect::<Vec<_>>()).await,
+        "issue_key" => {
+            let limit = |name| {
+                value_of(name)
+                    .map(|limit| match limit.parse::<u32>() {
+                        Ok(limit) if limit > 0 => Ok(limit as i64),
+                        _ => Err(ue!(
+                            format!("{} must be a positive whole number", name),
+                            Source::User
+                        )),
+                    })
+                    .transpose()
+            };
+
+            let origins = values_of("origin").collect::<Vec<_>>();
+
+            issue_key(
+                value_of("NAME").unwrap(),
+                limit("capacity")?,
+                limit("per_minute")?,
+                limit("daily_quota")?,
+                Some(origins).filter(|origins| !origins.is_empty()),
             )
             .await
         }
-        "trie_build" => {
-            trie_build(
-                op_matches.value_of("PATH").unwrap(),
-                op_matches.value_of("ID_PATH").unwrap(),
+        "list_keys" => list_keys().await,
+        "post" => post(values_of("ID")).await,
+        "purge_denied" => purge_denied(op_matches.get_flag("dry_run")).await,
+        "rank" => rank().await,
+        "revoke_key" => revoke_key(value_of("ID").unwrap().parse()?).await,
+        "save" => save(value_of("ID").unwrap()).await,
+        "search" => {
+            search(
+                value_of("LINK").unwrap(),
+                value_of("distance").map(|d| d.parse()).transpose()?,
+                op_matches.get_flag("transforms"),
             )
             .await
         }
+        "trie_build" => trie_build(value_of("PATH").unwrap(), value_of("ID_PATH").unwrap()).await,
         "trie_insert" => {
             trie_insert(
-                op_matches.value_of("PATH").unwrap(),
-                op_matches
-                    .values_of("HASHES")
-                    .unwrap()
+                value_of("PATH").unwrap(),
+                values_of("HASHES")
                     .try_fold(Vec::new(), |mut v, h| {
                         v.push(h.parse()?);
                         Ok::<_, UserError>(v)
@@ -0,0 +1,110 @@
+use super::limit::{self, Client};
+use super::search::{self, BatchResult, Findings, Search, SearchQuery};
+use common::*;
+use http::StatusCode;
+use serde::Serialize;
+use warp::multipart::FormData;
+use warp::Reply;
+
+#[derive(Serialize)]
+struct ApiSearch {
+    findings: Option<Findings>,
+    error: Option<UserError>,
+    ingest_state: Option<IngestState>,
+}
+
+#[derive(Serialize)]
+struct ApiBatch {
+    results: Vec<BatchResult>,
+    error: Option<UserError>,
+    ingest_state: Option<IngestState>,
+}
+
+/// Lets pages and extensions search from any origin, since which origins may use a key
+/// is checked with the key
+pub fn cors() -> warp::cors::Builder {
+    warp::cors()
+        .allow_any_origin()
+        .allow_headers(vec!["authorization"])
+        .allow_methods(vec!["GET", "POST"])
+}
+
+fn reply(search: Search) -> warp::reply::Response {
+    let Search {
+        findings,
+        error,
+        ingest_state,
+        ..
+    } = search;
+
+    // The HTML page shows an empty form here, but an API call without an image is a mistake
+    let error = match (&findings, error) {
+        (None, None) => Some(ue!("no image provided", Source::User)),
+        (_, error) => error,
+    };
+
+    let status = error
+        .as_ref()
+        .map(|ue| {
+            warn!("{}", ue.error);
+            ue.status_code()
+        })
+        .unwrap_or(StatusCode::OK);
+
+    warp::reply::with_status(
+        warp::reply::json(&ApiSearch {
+            findings,
+            error,
+            ingest_state,
+        }),
+        status,
+    )
+    .into_response()
+}
+
+pub async fn get_search_response(query: SearchQuery, client: Client) -> warp::reply::Response {
+    if let Err(refused) = limit::take(&client, 1).await {
+        return refused.json_response();
+    }
+
+    reply(search::get_search(query).await)
+}
+
+pub async fn post_search_response(form: FormData, client: Client) -> warp::reply::Response {
+    if let Err(refused) = limit::take(&client, 1).await {
+        return refused.json_response();
+    }
+
+    reply(search::post_search(form).await)
+}
+
+/// Each image in a batch costs a token, the first taken before the upload is read and the rest
+/// as they're searched
+pub async fn post_batch_response(form: FormData, client: Client) -> warp::reply::Response {
+    if let Err(refused) = limit::take(&client, 1).await {
+        return refused.json_response();
+    }
+
+    let (results, error) = match search::read_batch(form).await {
+        Ok(batch) => (batch.search(&client).await, None),
+        Err(error) => (Vec::new(), Some(error)),
+    };
+
+    let status = error
+        .as_ref()
+        .map(|ue| {
+            warn!("{}", ue.error);
+            ue.status_code()
+        })
+        .unwrap_or(StatusCode::OK);
+
+    warp::reply::with_status(
+        warp::reply::json(&ApiBatch {
+            results,
+            error,
+            ingest_state: search::read_ingest_state().await,
+        }),
+        status,
+    )
+    .into_response()
+}
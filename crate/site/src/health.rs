@@ -0,0 +1,86 @@
+use super::search::{ingest_state_fresh, read_ingest_state_file};
+use common::*;
+use http::StatusCode;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// How long the database gets to answer before the site counts as not ready
+const DATABASE_TIMEOUT_SECS: u64 = 5;
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+struct DatabaseCheck {
+    ok: bool,
+    took_ms: u128,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct IngestCheck {
+    fresh: bool,
+    as_of: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    database: DatabaseCheck,
+    ingest_state: IngestCheck,
+}
+
+pub fn get_healthz() -> impl warp::Reply {
+    warp::reply::json(&Health { status: "ok" })
+}
+
+async fn check_database() -> DatabaseCheck {
+    let start = Instant::now();
+
+    let res = tokio::time::timeout(Duration::from_secs(DATABASE_TIMEOUT_SECS), async {
+        PG_POOL.get().await?.query_one("SELECT 1", &[]).await?;
+        Ok::<_, UserError>(())
+    })
+    .await;
+
+    let error = match res {
+        Ok(Ok(())) => None,
+        Ok(Err(ue)) => Some(ue.error.to_string()),
+        Err(_) => Some("timed out".to_string()),
+    };
+
+    DatabaseCheck {
+        ok: error.is_none(),
+        took_ms: start.elapsed().as_millis(),
+        error,
+    }
+}
+
+/// Only the database decides readiness; a stopped ingester leaves search working
+pub async fn get_readyz() -> impl warp::Reply {
+    let database = check_database().await;
+    let ingest_state = read_ingest_state_file().await;
+
+    let readiness = Readiness {
+        ready: database.ok,
+        ingest_state: IngestCheck {
+            fresh: matches!(&ingest_state, Some(state) if ingest_state_fresh(state)),
+            as_of: ingest_state.map(|state| state.as_of),
+        },
+        database,
+    };
+
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        warn!(
+            "not ready: {}",
+            readiness.database.error.as_deref().unwrap_or("")
+        );
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    warp::reply::with_status(warp::reply::json(&readiness), status)
+}
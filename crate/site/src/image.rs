@@ -0,0 +1,217 @@
+use common::*;
+use http::StatusCode;
+use serde::Serialize;
+use tera::Context;
+
+#[derive(Clone, Serialize)]
+struct Post {
+    author: String,
+    created_utc: chrono::NaiveDateTime,
+    permalink: String,
+    score: i64,
+    subreddit: String,
+    title: String,
+}
+
+#[derive(Serialize)]
+struct SubredditCount {
+    subreddit: String,
+    posts: i64,
+    score: i64,
+}
+
+#[derive(Serialize)]
+struct Similar {
+    id: i64,
+    link: String,
+    distance: i64,
+    posts: i64,
+}
+
+#[derive(Serialize)]
+struct ImagePage {
+    id: i64,
+    link: String,
+    hash: String,
+    preview: String,
+    first: Option<Post>,
+    total: i64,
+    posts: Vec<Post>,
+    subreddits: Vec<SubredditCount>,
+    score_points: String,
+    similar: Vec<Similar>,
+    max_distance: u8,
+    ingest_state: Option<IngestState>,
+}
+
+/// Plots each post's score against when it was posted, scaled to a 100 by 40 SVG viewbox
+fn score_points(posts: &[Post]) -> String {
+    let (first, last) = match (posts.first(), posts.last()) {
+        (Some(first), Some(last)) => (first.created_utc, last.created_utc),
+        _ => return String::new(),
+    };
+
+    let span = (last - first).num_seconds().max(1) as f64;
+    let min_score = posts.iter().map(|post| post.score).min().unwrap_or(0);
+    let max_score = posts.iter().map(|post| post.score).max().unwrap_or(0);
+    let range = (max_score - min_score).max(1) as f64;
+
+    posts
+        .iter()
+        .map(|post| {
+            format!(
+                "{:.2},{:.2}",
+                (post.created_utc - first).num_seconds() as f64 / span * 100.,
+                40. - (post.score - min_score) as f64 / range * 40.
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Leaves out images that are taken down or denied by the `denied_hashes` table
+fn image_shown() -> String {
+    format!(
+        "NOT EXISTS (SELECT FROM takedowns WHERE takedowns.image_id = images.id) AND {}",
+        NOT_DENIED
+    )
+}
+
+async fn get_image(id: i64) -> Result<Option<ImagePage>, UserError> {
+    let denylist = denylist().await?;
+    let client = PG_POOL.get().await?;
+
+    let image = match client
+        .query_opt(
+            format!(
+                "SELECT link, hash FROM images WHERE id = $1 AND {}",
+                image_shown()
+            )
+            .as_str(),
+            &[&id],
+        )
+        .await?
+    {
+        Some(image) => image,
+        None => return Ok(None),
+    };
+
+    let link: String = image.get("link");
+    let hash: i64 = image.get("hash");
+
+    if denylist.matches_file(Hash(hash as u64)).is_some() {
+        return Ok(None);
+    }
+
+    // Only the earliest posts are listed, but every post is counted
+    let rows = client
+        .query(
+            "SELECT author, created_utc, permalink, score, subreddit, title, preview \
+             FROM posts WHERE image_id = $1 \
+             AND NOT EXISTS (SELECT FROM takedowns WHERE takedowns.post_id = posts.id) \
+             ORDER BY created_utc ASC, id ASC LIMIT $2",
+            &[&id, &CONFIG.max_results],
+        )
+        .await?;
+
+    let subreddits = client
+        .query(
+            "SELECT subreddit, COUNT(*) as posts, SUM(score)::bigint as score \
+             FROM posts WHERE image_id = $1 \
+             AND NOT EXISTS (SELECT FROM takedowns WHERE takedowns.post_id = posts.id) \
+             GROUP BY subreddit ORDER BY posts DESC, subreddit ASC",
+            &[&id],
+        )
+        .await?
+        .iter()
+        .map(|row| SubredditCount {
+            subreddit: row.get("subreddit"),
+            posts: row.get("posts"),
+            score: row.get("score"),
+        })
+        .collect::<Vec<_>>();
+    let total = subreddits.iter().map(|count| count.posts).sum();
+
+    let preview = rows
+        .iter()
+        .find_map(|row| row.get::<_, Option<String>>("preview"))
+        .map(|p| Submission::unescape(&p))
+        .unwrap_or_else(|| link.clone());
+
+    let posts = rows
+        .iter()
+        .map(|row| Post {
+            author: row.get("author"),
+            created_utc: row.get("created_utc"),
+            permalink: format!("https://reddit.com{}", row.get::<_, &str>("permalink")),
+            score: row.get("score"),
+            subreddit: row.get("subreddit"),
+            title: row.get("title"),
+        })
+        .collect::<Vec<_>>();
+
+    let similar = client
+        .query(
+            format!(
+                "SELECT id, link, hash, hash <-> $1 as distance, \
+                 (SELECT COUNT(*) FROM posts WHERE image_id = images.id \
+                 AND NOT EXISTS (SELECT FROM takedowns WHERE takedowns.post_id = posts.id)) \
+                 as posts \
+                 FROM images WHERE hash <@ ($1, $2) AND id <> $3 AND {} \
+                 ORDER BY distance ASC, id ASC LIMIT $4",
+                image_shown()
+            )
+            .as_str(),
+            &[
+                &hash,
+                &(CONFIG.max_distance as i64),
+                &id,
+                &CONFIG.max_results,
+            ],
+        )
+        .await?
+        .iter()
+        .filter(|row| {
+            denylist
+                .matches_file(Hash(row.get::<_, i64>("hash") as u64))
+                .is_none()
+        })
+        .map(|row| Similar {
+            id: row.get("id"),
+            link: row.get("link"),
+            distance: row.get("distance"),
+            posts: row.get("posts"),
+        })
+        .collect();
+
+    Ok(Some(ImagePage {
+        id,
+        link,
+        hash: Hash(hash as u64).to_string(),
+        preview,
+        first: posts.first().cloned(),
+        total,
+        score_points: score_points(&posts),
+        posts,
+        subreddits,
+        similar,
+        max_distance: CONFIG.max_distance,
+        ingest_state: super::search::read_ingest_state().await,
+    }))
+}
+
+pub async fn get_response(id: i64) -> Result<Option<impl warp::Reply>, UserError> {
+    let page = match get_image(id).await? {
+        Some(page) => page,
+        None => return Ok(None),
+    };
+
+    let tera = super::get_tera!();
+
+    let out = tera.render("image.html", &Context::from_serialize(&page)?)?;
+
+    Ok(Some(warp::reply::with_status(
+        warp::reply::html(out),
+        StatusCode::OK,
+    )))
+}
@@ -0,0 +1,234 @@
+use common::*;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use warp::http::{header, StatusCode};
+use warp::{Filter, Rejection, Reply};
+
+static PER_IP: Lazy<RateLimiter<IpAddr>> =
+    Lazy::new(|| RateLimiter::new(CONFIG.rate_limits.per_ip));
+static PER_KEY: Lazy<RateLimiter<i64>> = Lazy::new(|| RateLimiter::new(CONFIG.rate_limits.per_key));
+
+/// Who a request came from
+pub struct Client {
+    pub ip: Option<IpAddr>,
+    pub key: Option<String>,
+    pub origin: Option<String>,
+}
+
+/// The client a request came from, taking the address the proxy saw if it's trusted
+pub fn client() -> impl Filter<Extract = (Client,), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::header::optional::<String>("origin"))
+        .map(
+            |remote: Option<SocketAddr>,
+             forwarded_for: Option<String>,
+             authorization: Option<String>,
+             origin: Option<String>| {
+                let forwarded_ip = forwarded_for
+                    .filter(|_| CONFIG.rate_limits.trust_forwarded_for)
+                    .and_then(|forwarded_for| {
+                        forwarded_for.rsplit(',').next()?.trim().parse().ok()
+                    });
+
+                Client {
+                    ip: forwarded_ip.or_else(|| remote.map(|remote| remote.ip())),
+                    key: authorization
+                        .as_deref()
+                        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+                        .map(|key| key.trim().to_string()),
+                    origin,
+                }
+            },
+        )
+}
+
+/// Why a request was refused before it was searched
+pub enum Refused {
+    NoKey,
+    UnknownKey,
+    Origin,
+    RateLimited(Duration),
+    OverCapacity(u32),
+    QuotaUsed(Duration),
+    Error(UserError),
+}
+
+fn rate_limited(limit: &'static str) -> impl Fn(Shortfall) -> Refused {
+    move |shortfall| {
+        metrics::RATE_LIMITED.with_label_values(&[limit]).inc();
+        match shortfall {
+            Shortfall::Wait(retry_after) => Refused::RateLimited(retry_after),
+            Shortfall::OverCapacity(capacity) => Refused::OverCapacity(capacity),
+        }
+    }
+}
+
+/// The bucket an anonymous client's address uses. IPv6 clients share one per /64, since a
+/// single subscriber is usually given a whole /64 to pick addresses from.
+fn ip_bucket(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & !0 << 64)),
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// Until the next day's quota starts, at midnight UTC
+fn until_tomorrow() -> Duration {
+    let now = chrono::offset::Utc::now().naive_utc();
+    let tomorrow = (now.date() + chrono::Duration::days(1))
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    (tomorrow - now).to_std().unwrap_or_default()
+}
+
+/// Takes `cost` tokens from the client's bucket and counts them toward its key's quota.
+/// Clients with a key only use the key's bucket; anonymous ones use their address'.
+pub async fn take(client: &Client, cost: u32) -> Result<(), Refused> {
+    match &client.key {
+        Some(key) => take_key(client, key, cost).await.map(drop),
+        None => {
+            if let Some(ip) = client.ip {
+                PER_IP
+                    .take(ip_bucket(ip), cost)
+                    .map_err(rate_limited("ip"))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Takes `cost` tokens from `key`'s bucket and quota, returning the key's id
+async fn take_key(client: &Client, key: &str, cost: u32) -> Result<i64, Refused> {
+    let api_keys = api_keys().await.map_err(Refused::Error)?;
+    let api_key = api_keys.find(key).ok_or(Refused::UnknownKey)?;
+
+    if !api_key.allows_origin(client.origin.as_deref()) {
+        return Err(Refused::Origin);
+    }
+
+    PER_KEY
+        .take_with_limits(api_key.id, cost, api_key.limits())
+        .map_err(rate_limited("key"))?;
+
+    // The tokens are given back if the quota refuses, so they aren't spent on nothing
+    match count_usage(api_key, cost as i64).await {
+        Ok(true) => Ok(api_key.id),
+        Ok(false) => {
+            PER_KEY.give_back(api_key.id, cost);
+            metrics::RATE_LIMITED.with_label_values(&["quota"]).inc();
+            Err(Refused::QuotaUsed(until_tomorrow()))
+        }
+        Err(ue) => {
+            PER_KEY.give_back(api_key.id, cost);
+            Err(Refused::Error(ue))
+        }
+    }
+}
+
+/// Like `take`, but refuses anonymous clients and returns the id of the key it charged
+pub async fn take_keyed(client: &Client, cost: u32) -> Result<i64, Refused> {
+    match &client.key {
+        Some(key) => take_key(client, key, cost).await,
+        None => Err(Refused::NoKey),
+    }
+}
+
+#[derive(Serialize)]
+struct ApiRefused {
+    error: UserError,
+}
+
+/// Whole seconds, rounded up so that a retry won't come too soon
+fn retry_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs() + (retry_after.subsec_nanos() > 0) as u64
+}
+
+impl Refused {
+    pub fn user_error(self) -> UserError {
+        match self {
+            Refused::NoKey => ue!("an API key is required", Source::User),
+            Refused::UnknownKey => ue!("unknown or revoked API key", Source::User),
+            Refused::Origin => ue!("API key not allowed from this origin", Source::User),
+            Refused::RateLimited(retry_after) => ue!(
+                format!(
+                    "too many searches, try again in {} seconds",
+                    retry_secs(retry_after)
+                ),
+                Source::User
+            ),
+            Refused::OverCapacity(capacity) => ue!(
+                format!(
+                    "too many searches at once, the limit is {} per request",
+                    capacity
+                ),
+                Source::User
+            ),
+            Refused::QuotaUsed(_) => ue!("daily quota used up", Source::User),
+            Refused::Error(ue) => ue,
+        }
+    }
+
+    fn respond(
+        self,
+        make_reply: impl FnOnce(UserError) -> warp::reply::Response,
+    ) -> warp::reply::Response {
+        let (status, retry_after) = match &self {
+            Refused::NoKey | Refused::UnknownKey => (StatusCode::UNAUTHORIZED, None),
+            Refused::Origin => (StatusCode::FORBIDDEN, None),
+            Refused::RateLimited(retry_after) | Refused::QuotaUsed(retry_after) => {
+                (StatusCode::TOO_MANY_REQUESTS, Some(*retry_after))
+            }
+            Refused::OverCapacity(_) => (StatusCode::TOO_MANY_REQUESTS, None),
+            Refused::Error(ue) => (ue.status_code(), None),
+        };
+
+        let error = self.user_error();
+        warn!("{}", error.error);
+
+        let mut response = make_reply(error);
+        *response.status_mut() = status;
+        if let Some(retry_after) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_secs(retry_after).into());
+        }
+        response
+    }
+
+    pub fn html_response(self) -> warp::reply::Response {
+        self.respond(|error| error.user_msg.into_owned().into_response())
+    }
+
+    pub fn json_response(self) -> warp::reply::Response {
+        self.respond(|error| warp::reply::json(&ApiRefused { error }).into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ip_buckets() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        assert_eq!(ip_bucket(ip("203.0.113.7")), ip("203.0.113.7"));
+        assert_eq!(ip_bucket(ip("2001:db8:1:2:3:4:5:6")), ip("2001:db8:1:2::"));
+        assert_eq!(
+            ip_bucket(ip("2001:db8:1:2:ffff::1")),
+            ip_bucket(ip("2001:db8:1:2::9"))
+        );
+        assert_ne!(
+            ip_bucket(ip("2001:db8:1:2::1")),
+            ip_bucket(ip("2001:db8:1:3::1"))
+        );
+        assert_eq!(ip_bucket(ip("::ffff:203.0.113.7")), ip("203.0.113.7"));
+    }
+}
@@ -6,11 +6,21 @@ use once_cell::sync::Lazy;
 use warp::filters::*;
 use warp::http::{header, Response, StatusCode};
 use warp::path::path;
-use warp::{Filter, Rejection};
+use warp::{Filter, Rejection, Reply};
 
+mod admin;
+mod api;
+mod health;
+mod image;
+mod limit;
+mod profile;
+use profile::ProfileKind;
 mod search;
+mod sql;
 use search::SearchQuery;
 mod rankings;
+use rankings::RankingsQuery;
+mod watch;
 
 mod render;
 
@@ -19,39 +29,204 @@ struct UEReject(UserError);
 
 impl warp::reject::Reject for UEReject {}
 
+/// Replies with a page, or rejects if it doesn't exist or couldn't be made
+fn found<T>(page: Result<Option<T>, UserError>) -> Result<T, Rejection> {
+    match page {
+        Ok(Some(reply)) => Ok(reply),
+        Ok(None) => Err(warp::reject::not_found()),
+        Err(ue) => {
+            warn!("{}", ue.error);
+            Err(warp::reject::custom(UEReject(ue)))
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt::init();
 
     Lazy::force(&render::TERA);
 
+    // Metrics are served on their own listener rather than among the public routes
+    metrics::listen("site");
+
     let head = method::head().map(|| StatusCode::OK);
 
     let router = warp::path::end()
         .and(
             method::get()
-                .and(query::query::<SearchQuery>().and_then(|query| async {
-                    Ok::<_, Rejection>(search::get_response(query).await)
-                }))
+                .and(query::query::<SearchQuery>())
+                .and(limit::client())
+                .and_then(|query: SearchQuery, client| async move {
+                    if query.searches() {
+                        if let Err(refused) = limit::take(&client, 1).await {
+                            return Ok::<_, Rejection>(refused.html_response());
+                        }
+                    }
+
+                    Ok(search::get_response(query).await.into_response())
+                })
                 .or(method::post()
-                    .and(multipart::form())
-                    .and_then(|form| async move {
-                        Ok::<_, Rejection>(search::post_response(form).await)
+                    .and(multipart::form().max_length(CONFIG.rate_limits.max_upload))
+                    .and(limit::client())
+                    .and_then(|form, client| async move {
+                        if let Err(refused) = limit::take(&client, 1).await {
+                            return Ok::<_, Rejection>(refused.html_response());
+                        }
+
+                        Ok(search::post_response(form).await.into_response())
                     }))
                 .or(head),
         )
         .or(path("rankings").and(
             method::get()
-                .and_then(|| async {
-                    rankings::get_response()
+                .and(query::query::<RankingsQuery>())
+                .and(limit::client())
+                .and_then(|query, client| async move {
+                    if let Err(refused) = limit::take(&client, 1).await {
+                        return Ok(refused.html_response());
+                    }
+
+                    rankings::get_response(query)
+                        .map_ok(Reply::into_response)
                         .map_err(|ue| {
-                            println!("{:?}", ue);
+                            warn!("{}", ue.error);
                             warp::reject::custom(UEReject(ue))
                         })
                         .await
                 })
                 .or(head),
         ))
+        .or(path("image").and(
+            warp::path::param::<i64>()
+                .and(warp::path::end())
+                .and(method::get())
+                .and(limit::client())
+                .and_then(|id, client| async move {
+                    if let Err(refused) = limit::take(&client, 1).await {
+                        return Ok(refused.html_response());
+                    }
+
+                    found(image::get_response(id).await).map(Reply::into_response)
+                }),
+        ))
+        .or(path("u").and(
+            warp::path::param::<String>()
+                .and(warp::path::end())
+                .and(method::get())
+                .and(limit::client())
+                .and_then(|name, client| async move {
+                    if let Err(refused) = limit::take(&client, 1).await {
+                        return Ok(refused.html_response());
+                    }
+
+                    found(profile::get_response(ProfileKind::Author, name).await)
+                        .map(Reply::into_response)
+                }),
+        ))
+        .or(path("r").and(
+            warp::path::param::<String>()
+                .and(warp::path::end())
+                .and(method::get())
+                .and(limit::client())
+                .and_then(|name, client| async move {
+                    if let Err(refused) = limit::take(&client, 1).await {
+                        return Ok(refused.html_response());
+                    }
+
+                    found(profile::get_response(ProfileKind::Subreddit, name).await)
+                        .map(Reply::into_response)
+                }),
+        ))
+        .or(path("admin").and(
+            warp::path::end()
+                .and(method::get())
+                .and(warp::header::optional::<String>("authorization"))
+                .and_then(|authorization| async move {
+                    Ok::<_, Rejection>(admin::get_response(authorization).await)
+                })
+                .or(warp::path::param::<String>()
+                    .and(warp::path::end())
+                    .and(method::post())
+                    .and(warp::header::optional::<String>("authorization"))
+                    .and(warp::header::optional::<String>("origin"))
+                    .and(warp::header::optional::<String>("referer"))
+                    .and(warp::header::optional::<String>("host"))
+                    .and(body::form())
+                    .and_then(
+                        |action, authorization, origin, referer, host, form| async move {
+                            Ok::<_, Rejection>(
+                                admin::post_response(
+                                    action,
+                                    authorization,
+                                    origin,
+                                    referer,
+                                    host,
+                                    form,
+                                )
+                                .await,
+                            )
+                        },
+                    )),
+        ))
+        .or(path("watch").and(
+            warp::path::param::<String>()
+                .and(path("feed"))
+                .and(warp::path::end())
+                .and(method::get())
+                .and_then(|token| async move { found(watch::feed_response(token).await) }),
+        ))
+        .or(path("api").and(path("v1")).and(
+            path("search")
+                .and(warp::path::end())
+                .and(
+                    method::get()
+                        .and(query::query::<SearchQuery>())
+                        .and(limit::client())
+                        .and_then(|query, client| async move {
+                            Ok::<_, Rejection>(api::get_search_response(query, client).await)
+                        })
+                        .or(method::post()
+                            .and(multipart::form().max_length(CONFIG.rate_limits.max_upload))
+                            .and(limit::client())
+                            .and_then(|form, client| async move {
+                                Ok::<_, Rejection>(api::post_search_response(form, client).await)
+                            })),
+                )
+                .or(path("batch").and(warp::path::end()).and(
+                    method::post()
+                        .and(multipart::form().max_length(CONFIG.batch_limits.max_length))
+                        .and(limit::client())
+                        .and_then(|form, client| async move {
+                            Ok::<_, Rejection>(api::post_batch_response(form, client).await)
+                        }),
+                ))
+                .with(api::cors())
+                .or(path("watches").and(
+                    warp::path::end()
+                        .and(method::post())
+                        .and(body::form())
+                        .and(limit::client())
+                        .and_then(|form, client| async move {
+                            Ok::<_, Rejection>(watch::post_response(form, client).await)
+                        })
+                        .or(warp::path::param::<String>()
+                            .and(warp::path::end())
+                            .and(method::delete())
+                            .and(limit::client())
+                            .and_then(|token, client| async move {
+                                found(watch::delete_response(token, client).await)
+                            })),
+                )),
+        ))
+        .or(path("healthz")
+            .and(warp::path::end())
+            .and(method::get().or(method::head()).unify())
+            .map(health::get_healthz))
+        .or(path("readyz")
+            .and(warp::path::end())
+            .and(method::get().or(method::head()).unify())
+            .and_then(|| async { Ok::<_, Rejection>(health::get_readyz().await) }))
         .or(path("robots.txt").and(
             method::get()
                 .and_then(|| async {
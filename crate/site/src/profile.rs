@@ -0,0 +1,207 @@
+use super::sql;
+use common::*;
+use http::StatusCode;
+use serde::Serialize;
+use tera::Context;
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileKind {
+    Author,
+    Subreddit,
+}
+
+impl ProfileKind {
+    fn column(self) -> &'static str {
+        match self {
+            ProfileKind::Author => "author",
+            ProfileKind::Subreddit => "subreddit",
+        }
+    }
+
+    fn prefix(self) -> &'static str {
+        match self {
+            ProfileKind::Author => "/u/",
+            ProfileKind::Subreddit => "/r/",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ProfilePost {
+    author: String,
+    created_utc: chrono::NaiveDateTime,
+    image_id: i64,
+    permalink: String,
+    preview: String,
+    score: i64,
+    subreddit: String,
+    title: String,
+    posted_before: i64,
+    posted_after: i64,
+}
+
+#[derive(Serialize)]
+struct Profile {
+    kind: ProfileKind,
+    prefix: &'static str,
+    name: String,
+    total: i64,
+    shown: i64,
+    original: i64,
+    reposted: i64,
+    original_percent: i64,
+    posts: Vec<ProfilePost>,
+    ingest_state: Option<IngestState>,
+}
+
+/// How long each of a profile's queries may run for
+const STATEMENT_TIMEOUT_MS: u64 = 5000;
+
+/// Posts of any image with the same hash as a post's, so a copy uploaded to another host still
+/// counts as the same image, that came before or after it by `comparison`
+fn same_image_posts(comparison: &str) -> String {
+    format!(
+        "FROM posts AS other INNER JOIN images AS other_image ON other.image_id = other_image.id \
+         WHERE other_image.hash <@ (images.hash, 0) \
+         AND (other.created_utc, other.id) {} (posts.created_utc, posts.id)",
+        comparison
+    )
+}
+
+async fn get_profile(kind: ProfileKind, name: String) -> Result<Option<Profile>, UserError> {
+    let denylist = denylist().await?;
+    let mut client = PG_POOL.get().await?;
+
+    // Every post's history is looked up, so a page of a busy profile could otherwise run long
+    let trans = client.transaction().await?;
+    trans
+        .execute(
+            format!("SET LOCAL statement_timeout = {}", STATEMENT_TIMEOUT_MS).as_str(),
+            &[],
+        )
+        .await?;
+
+    // Names are matched however they're capitalized, and taken down or denied posts are left out
+    let conditions = format!(
+        "LOWER({}) = LOWER($1) \
+         AND NOT EXISTS (SELECT FROM takedowns WHERE takedowns.post_id = posts.id) \
+         AND NOT EXISTS (SELECT FROM takedowns WHERE takedowns.image_id = images.id) \
+         AND {}",
+        kind.column(),
+        NOT_DENIED
+    );
+
+    let total: i64 = trans
+        .query_one(
+            format!(
+                "SELECT COUNT(*) as total \
+                 FROM posts INNER JOIN images ON image_id = images.id \
+                 WHERE {}",
+                conditions
+            )
+            .as_str(),
+            &[&name],
+        )
+        .await
+        .map_err(sql::timed_out)?
+        .get("total");
+
+    if total == 0 {
+        return Ok(None);
+    }
+
+    let rows = trans
+        .query(
+            format!(
+                "SELECT author, created_utc, image_id, permalink, preview, \
+                 images.link as link, images.hash as image_hash, score, subreddit, title, \
+                 (SELECT COUNT(*) {}) as posted_before, \
+                 (SELECT COUNT(*) {}) as posted_after \
+                 FROM posts INNER JOIN images ON image_id = images.id \
+                 WHERE {} \
+                 ORDER BY created_utc DESC, posts.id DESC LIMIT $2",
+                same_image_posts("<"),
+                same_image_posts(">"),
+                conditions
+            )
+            .as_str(),
+            &[&name, &CONFIG.max_results],
+        )
+        .await
+        .map_err(sql::timed_out)?;
+
+    trans.commit().await?;
+
+    // Shown as it's capitalized on Reddit, rather than as it was typed
+    let name = rows
+        .first()
+        .map(|row| row.get(kind.column()))
+        .unwrap_or(name);
+
+    let posts = rows
+        .iter()
+        .filter(|row| {
+            denylist
+                .matches_file(Hash(row.get::<_, i64>("image_hash") as u64))
+                .is_none()
+        })
+        .map(|row| ProfilePost {
+            author: row.get("author"),
+            created_utc: row.get("created_utc"),
+            image_id: row.get("image_id"),
+            permalink: format!("https://reddit.com{}", row.get::<_, &str>("permalink")),
+            preview: row
+                .get::<_, Option<String>>("preview")
+                .map(|p| Submission::unescape(&p))
+                .unwrap_or_else(|| row.get("link")),
+            score: row.get("score"),
+            subreddit: row.get("subreddit"),
+            title: row.get("title"),
+            posted_before: row.get("posted_before"),
+            posted_after: row.get("posted_after"),
+        })
+        .collect::<Vec<_>>();
+
+    // A post is original if no earlier post used its image. Only the shown posts are counted,
+    // since looking up the history of every post of a large subreddit is too slow, and the
+    // page says so.
+    let shown = posts.len() as i64;
+    let original = posts.iter().filter(|post| post.posted_before == 0).count() as i64;
+
+    Ok(Some(Profile {
+        kind,
+        prefix: kind.prefix(),
+        name,
+        total,
+        shown,
+        original,
+        reposted: shown - original,
+        original_percent: if shown == 0 {
+            0
+        } else {
+            original * 100 / shown
+        },
+        posts,
+        ingest_state: super::search::read_ingest_state().await,
+    }))
+}
+
+pub async fn get_response(
+    kind: ProfileKind,
+    name: String,
+) -> Result<Option<impl warp::Reply>, UserError> {
+    let profile = match get_profile(kind, name).await? {
+        Some(profile) => profile,
+        None => return Ok(None),
+    };
+
+    let tera = super::get_tera!();
+
+    let out = tera.render("profile.html", &Context::from_serialize(&profile)?)?;
+
+    Ok(Some(warp::reply::with_status(
+        warp::reply::html(out),
+        StatusCode::OK,
+    )))
+}
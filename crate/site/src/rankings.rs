@@ -1,22 +1,196 @@
+use super::sql::QueryBuilder;
+use chrono::{Duration, Utc};
 use common::*;
 use http::StatusCode;
-use serde::Serialize;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
 use tera::Context;
 
-#[derive(Serialize)]
+/// How long a ranking is served before it's counted again
+const CACHE_SECS: u64 = 60;
+
+/// The most rankings kept at once, since any subreddit can be asked for
+const CACHE_MAX: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct RankingsQuery {
+    window: Option<String>,
+    subreddit: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Window {
+    Day,
+    Week,
+    Month,
+    All,
+}
+
+impl std::str::FromStr for Window {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use Window::*;
+        match s {
+            "day" => Ok(Day),
+            "" | "week" => Ok(Week),
+            "month" => Ok(Month),
+            "all" => Ok(All),
+            _ => Err(format_err!("Invalid window: {}", s)),
+        }
+    }
+}
+
+impl Window {
+    /// The first day counted. Counts are kept by calendar day, so a window is that many days
+    /// ending with today.
+    fn start(self, today: chrono::NaiveDate) -> Option<chrono::NaiveDate> {
+        let days = match self {
+            Window::Day => 1,
+            Window::Week => 7,
+            Window::Month => 30,
+            Window::All => return None,
+        };
+
+        Some(today - Duration::days(days - 1))
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct RankedImage {
+    num: i64,
+    image_id: i64,
+    link: String,
+    preview: String,
+}
+
+#[derive(Clone, Serialize)]
 struct Rankings {
     as_of: String,
-    common_images: Vec<CommonImage>,
+    window: Window,
+    subreddit: String,
+    common_images: Vec<RankedImage>,
 }
 
-pub async fn get_response() -> Result<impl warp::Reply, UserError> {
-    let images: CommonImages = ron::de::from_reader(std::fs::File::open(
-        std::env::var("HOME")? + "/stats/top100.ron",
-    )?)?;
+type RankingsCache = HashMap<(Window, String), (Instant, Rankings)>;
+
+static CACHE: Lazy<Mutex<RankingsCache>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
-    let rankings = Rankings {
-        as_of: images.as_of.format("%F %T %Z").to_string(),
-        common_images: images.common_images,
+async fn rank(window: Window, subreddit: String) -> Result<Rankings, UserError> {
+    let mut query = QueryBuilder::new();
+
+    if let Some(start) = window.start(Utc::now().naive_utc().date()) {
+        let start_arg = query.arg(start);
+        query.and(format!("day >= {}", start_arg));
+    }
+    if !subreddit.is_empty() {
+        let subreddit_arg = query.arg(subreddit.clone());
+        query.and(format!("subreddit = {}", subreddit_arg));
+    }
+
+    // Every post ever is kept totalled by hash, rather than summed from each day's counts.
+    // Counts can drop to 0 when their posts are taken down.
+    let ranked = if window == Window::All && subreddit.is_empty() {
+        "SELECT hash, num FROM image_totals WHERE num > 0 ORDER BY num DESC LIMIT 100".to_string()
+    } else {
+        format!(
+            "SELECT hash, SUM(num)::bigint as num FROM image_counts \
+             WHERE TRUE{} GROUP BY hash HAVING SUM(num) > 0 ORDER BY num DESC LIMIT 100",
+            query.conditions()
+        )
+    };
+
+    let rows = PG_POOL
+        .get()
+        .await?
+        .query(
+            format!(
+                "SELECT ranked.num, image.id, image.link, \
+                 (SELECT preview FROM posts \
+                 WHERE image_id = image.id AND preview IS NOT NULL \
+                 AND NOT EXISTS (SELECT FROM takedowns WHERE takedowns.post_id = posts.id) \
+                 LIMIT 1) as preview \
+                 FROM ({}) ranked \
+                 CROSS JOIN LATERAL (SELECT id, link FROM images \
+                 WHERE hash <@ (ranked.hash, 0) \
+                 AND NOT EXISTS (SELECT FROM takedowns WHERE takedowns.image_id = images.id) \
+                 AND {} \
+                 ORDER BY id LIMIT 1) image \
+                 ORDER BY ranked.num DESC, image.id ASC",
+                ranked, NOT_DENIED
+            )
+            .as_str(),
+            &query.args(),
+        )
+        .await?;
+
+    Ok(Rankings {
+        as_of: Utc::now().format("%F %T %Z").to_string(),
+        window,
+        subreddit,
+        common_images: rows
+            .iter()
+            .map(|row| {
+                let link: String = row.get("link");
+                RankedImage {
+                    num: row.get("num"),
+                    image_id: row.get("id"),
+                    preview: row
+                        .get::<_, Option<String>>("preview")
+                        .map(|p| Submission::unescape(&p))
+                        .unwrap_or_else(|| link.clone()),
+                    link,
+                }
+            })
+            .collect(),
+    })
+}
+
+pub async fn get_response(query: RankingsQuery) -> Result<impl warp::Reply, UserError> {
+    let window: Window = query
+        .window
+        .as_deref()
+        .unwrap_or_default()
+        .parse()
+        .map_err(map_ue!("invalid window parameter", Source::User))?;
+    let subreddit = query.subreddit.unwrap_or_default().trim().to_lowercase();
+    let key = (window, subreddit);
+
+    let cached = CACHE
+        .lock()
+        .unwrap()
+        .get(&key)
+        .filter(|(at, _)| at.elapsed().as_secs() < CACHE_SECS)
+        .map(|(_, rankings)| rankings.clone());
+
+    let rankings = match cached {
+        Some(rankings) => rankings,
+        None => {
+            let rankings = rank(key.0, key.1.clone()).await?;
+
+            // Subreddits with nothing counted are kept too, so asking for made-up ones doesn't
+            // count them every time. When the cache is full the expired rankings go first,
+            // then the oldest.
+            let mut cache = CACHE.lock().unwrap();
+            if cache.len() >= CACHE_MAX {
+                cache.retain(|_, (at, _)| at.elapsed().as_secs() < CACHE_SECS);
+            }
+            if cache.len() >= CACHE_MAX {
+                if let Some(oldest) = cache
+                    .iter()
+                    .min_by_key(|(_, (at, _))| *at)
+                    .map(|(key, _)| key.clone())
+                {
+                    cache.remove(&oldest);
+                }
+            }
+            cache.insert(key, (Instant::now(), rankings.clone()));
+            rankings
+        }
     };
 
     let tera = super::get_tera!();
@@ -28,3 +202,20 @@ pub async fn get_response() -> Result<impl warp::Reply, UserError> {
         StatusCode::OK,
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_start() {
+        let today = chrono::NaiveDate::from_ymd_opt(2020, 3, 2).unwrap();
+
+        assert_eq!(Window::Day.start(today), Some(today));
+        assert_eq!(
+            Window::Week.start(today),
+            chrono::NaiveDate::from_ymd_opt(2020, 2, 25)
+        );
+        assert_eq!(Window::All.start(today), None);
+    }
+}
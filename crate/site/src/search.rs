@@ -6,22 +6,44 @@ use futures::prelude::*;
 use http::StatusCode;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::error::Error as _;
 use std::io::Read;
 use std::time::Instant;
 use std::vec::Vec;
 use tera::Context;
-use tokio_postgres::error::{DbError, SqlState};
+
+use super::limit::{self, Client};
+use super::sql::{self, QueryBuilder};
 use url::Url;
 use warp::multipart::FormData;
 
 #[derive(Deserialize)]
 pub struct SearchQuery {
     imagelink: Option<String>,
+    hash: Option<String>,
     distance: Option<String>,
+    wide_distance: Option<String>,
     nsfw: Option<String>,
     subreddits: Option<String>,
     authors: Option<String>,
+    before: Option<String>,
+    after: Option<String>,
+    min_score: Option<String>,
+    spoiler: Option<String>,
+    video: Option<String>,
+    cursor: Option<String>,
+    group: Option<String>,
+    agree: Option<String>,
+    transforms: Option<String>,
+    frames: Option<String>,
+}
+
+impl SearchQuery {
+    /// Whether this asks for a search, rather than just the search page
+    pub fn searches(&self) -> bool {
+        [&self.imagelink, &self.hash]
+            .iter()
+            .any(|field| matches!(field, Some(value) if !value.is_empty()))
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize)]
@@ -52,103 +74,547 @@ impl Default for NSFWOption {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum FlagOption {
+    Include,
+    Exclude,
+    Only,
+}
+
+impl FlagOption {
+    fn condition(&self, column: &str) -> Option<String> {
+        match self {
+            FlagOption::Include => None,
+            FlagOption::Exclude => Some(format!("{} IS NOT TRUE", column)),
+            FlagOption::Only => Some(format!("{} IS TRUE", column)),
+        }
+    }
+}
+
+impl std::str::FromStr for FlagOption {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use FlagOption::*;
+        match s {
+            "" | "include" => Ok(Include),
+            "exclude" => Ok(Exclude),
+            "only" => Ok(Only),
+            _ => Err(format_err!("Invalid option: {}", s)),
+        }
+    }
+}
+
+/// A list of names to include or exclude, like `pics -funny art*`
+#[derive(Clone, Debug, Default)]
+struct NameFilter {
+    include: Vec<String>,
+    include_prefixes: Vec<String>,
+    exclude: Vec<String>,
+    exclude_prefixes: Vec<String>,
+}
+
+impl NameFilter {
+    fn like_prefix(prefix: &str) -> String {
+        format!(
+            "{}%",
+            prefix
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        )
+    }
+
+    fn apply(self, column: &str, query: &mut QueryBuilder) {
+        let column = format!("LOWER({})", column);
+
+        let mut included = Vec::new();
+        if !self.include.is_empty() {
+            included.push(format!("{} = ANY({})", column, query.arg(self.include)));
+        }
+        if !self.include_prefixes.is_empty() {
+            let patterns = self
+                .include_prefixes
+                .iter()
+                .map(|prefix| Self::like_prefix(prefix))
+                .collect::<Vec<_>>();
+            included.push(format!("{} LIKE ANY({})", column, query.arg(patterns)));
+        }
+        if !included.is_empty() {
+            query.and(format!("({})", included.join(" OR ")));
+        }
+
+        if !self.exclude.is_empty() {
+            let exclude_arg = query.arg(self.exclude);
+            query.and(format!("{} <> ALL({})", column, exclude_arg));
+        }
+        if !self.exclude_prefixes.is_empty() {
+            let patterns = self
+                .exclude_prefixes
+                .iter()
+                .map(|prefix| Self::like_prefix(prefix))
+                .collect::<Vec<_>>();
+            let patterns_arg = query.arg(patterns);
+            query.and(format!("{} NOT LIKE ALL({})", column, patterns_arg));
+        }
+    }
+}
+
+impl std::str::FromStr for NameFilter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = NameFilter::default();
+
+        for term in s.split_whitespace().map(str::to_lowercase) {
+            let (negated, name) = match term.strip_prefix('-') {
+                Some(name) => (true, name),
+                None => (false, term.as_str()),
+            };
+
+            let (prefix, name) = match name.strip_suffix('*') {
+                Some(name) => (true, name),
+                None => (false, name),
+            };
+
+            if name.is_empty() || name.contains('*') {
+                return Err(format_err!("Invalid name filter: {}", term));
+            }
+
+            let name = name.to_string();
+
+            match (negated, prefix) {
+                (false, false) => filter.include.push(name),
+                (false, true) => filter.include_prefixes.push(name),
+                (true, false) => filter.exclude.push(name),
+                (true, true) => filter.exclude_prefixes.push(name),
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+fn parse_date(date: &str) -> Result<Option<chrono::NaiveDateTime>, Error> {
+    if date.is_empty() {
+        Ok(None)
+    } else {
+        Ok(chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")?.and_hms_opt(0, 0, 0))
+    }
+}
+
+/// The position after the last match of a page, in the order results are sorted
+#[derive(Clone, Debug, PartialEq)]
+struct Cursor {
+    rank: i64,
+    distance: i64,
+    created_utc: chrono::NaiveDateTime,
+    id: i64,
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}_{}_{}_{}",
+            self.rank,
+            self.distance,
+            self.created_utc.timestamp(),
+            self.id
+        )
+    }
+}
+
+impl std::str::FromStr for Cursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(4, '_');
+        let mut next = || {
+            parts
+                .next()
+                .ok_or_else(|| format_err!("Incomplete cursor: {}", s))?
+                .parse::<i64>()
+                .map_err(Error::from)
+        };
+
+        Ok(Cursor {
+            rank: next()?,
+            distance: next()?,
+            created_utc: chrono::NaiveDateTime::from_timestamp_opt(next()?, 0)
+                .ok_or_else(|| format_err!("Invalid cursor date: {}", s))?,
+            id: next()?,
+        })
+    }
+}
+
 #[derive(Debug, Serialize)]
-struct Match {
+pub struct Match {
     author: Option<String>,
     created_utc: chrono::NaiveDateTime,
     distance: i64,
+    /// Between the 256-bit hashes, if both images have one
+    wide_distance: Option<i64>,
+    /// The flip or rotation of the query that matched, if it wasn't the query itself
+    transform: Option<String>,
+    /// Whether it was found once borders were trimmed from it or the query
+    trimmed: bool,
+    /// Whether it was found through a frame of an animation, either its own or the query's
+    frame: bool,
     link: String,
     preview: String,
     permalink: String,
     score: i64,
     subreddit: String,
     title: String,
+    image_id: i64,
+    #[serde(skip)]
+    reddit_id_int: i64,
+    #[serde(skip)]
+    crosspost_parent: Option<i64>,
+}
+
+/// Posts of the same image or crosspost chain
+#[derive(Debug, Serialize)]
+pub struct Group {
+    earliest: Match,
+    count: usize,
+    members: Vec<Match>,
 }
 
 #[derive(Debug, Serialize)]
-struct Findings {
+pub struct Findings {
     took: String,
     matches: Vec<Match>,
+    groups: Option<Vec<Group>>,
+    next_cursor: Option<String>,
+}
+
+/// Groups matches that share an image or are linked through crossposts, keeping the order
+/// of each group's first match. Only the matches given are grouped, so a group can be
+/// split across pages.
+fn group_matches(matches: Vec<Match>) -> Vec<Group> {
+    #[derive(PartialEq, Eq, Hash)]
+    enum Node {
+        Image(i64),
+        Post(i64),
+    }
+
+    fn find(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    let mut indices = HashMap::new();
+    let mut parents = Vec::new();
+
+    let firsts = matches
+        .iter()
+        .map(|m| {
+            let nodes = std::iter::once(Node::Image(m.image_id))
+                .chain(std::iter::once(Node::Post(m.reddit_id_int)))
+                .chain(m.crosspost_parent.map(Node::Post))
+                .map(|node| {
+                    let next = parents.len();
+                    let i = *indices.entry(node).or_insert(next);
+                    if i == next {
+                        parents.push(next);
+                    }
+                    i
+                })
+                .collect::<Vec<_>>();
+
+            let first = find(&mut parents, nodes[0]);
+            for &node in &nodes[1..] {
+                let root = find(&mut parents, node);
+                parents[root] = first;
+            }
+
+            nodes[0]
+        })
+        .collect::<Vec<_>>();
+
+    let mut group_indices = HashMap::new();
+    let mut groups: Vec<Vec<Match>> = Vec::new();
+
+    for (m, first) in matches.into_iter().zip(firsts) {
+        let root = find(&mut parents, first);
+        let i = *group_indices.entry(root).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[i].push(m);
+    }
+
+    groups
+        .into_iter()
+        .map(|mut members| {
+            members.sort_by_key(|m| (m.created_utc, m.reddit_id_int));
+            let earliest = members.remove(0);
+
+            Group {
+                earliest,
+                count: members.len() + 1,
+                members,
+            }
+        })
+        .collect()
 }
 
 #[derive(Clone, Debug, Serialize)]
 struct Form {
     link: String,
+    hash: String,
     distance: String,
+    wide_distance: String,
     nsfw: String,
     subreddits: String,
     authors: String,
+    before: String,
+    after: String,
+    min_score: String,
+    spoiler: String,
+    video: String,
+    cursor: String,
+    group: String,
+    agree: String,
+    transforms: String,
+    frames: String,
 }
 
 impl Default for Form {
     fn default() -> Form {
         Form {
             link: "".to_string(),
+            hash: "".to_string(),
             distance: "1".to_string(),
+            wide_distance: "".to_string(),
             nsfw: "allow".to_string(),
             subreddits: "".to_string(),
             authors: "".to_string(),
+            before: "".to_string(),
+            after: "".to_string(),
+            min_score: "".to_string(),
+            spoiler: "include".to_string(),
+            video: "include".to_string(),
+            cursor: "".to_string(),
+            group: "".to_string(),
+            agree: "".to_string(),
+            transforms: "".to_string(),
+            frames: "".to_string(),
         }
     }
 }
 
+impl Form {
+    fn from_map(map: &HashMap<String, Vec<u8>>) -> Form {
+        #[allow(clippy::ptr_arg)]
+        fn utf8_to_string(utf8: &Vec<u8>) -> String {
+            String::from_utf8_lossy(utf8.as_slice()).to_string()
+        }
+
+        let default_form = Form::default();
+        Form {
+            distance: map
+                .get("distance")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.distance),
+            wide_distance: map
+                .get("wide_distance")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.wide_distance),
+            nsfw: map
+                .get("nsfw")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.nsfw),
+            subreddits: map
+                .get("subreddits")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.subreddits),
+            authors: map
+                .get("authors")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.authors),
+            before: map
+                .get("before")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.before),
+            after: map
+                .get("after")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.after),
+            min_score: map
+                .get("min_score")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.min_score),
+            spoiler: map
+                .get("spoiler")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.spoiler),
+            video: map
+                .get("video")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.video),
+            cursor: map
+                .get("cursor")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.cursor),
+            hash: map
+                .get("hash")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.hash),
+            group: map
+                .get("group")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.group),
+            agree: map
+                .get("agree")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.agree),
+            transforms: map
+                .get("transforms")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.transforms),
+            frames: map
+                .get("frames")
+                .map(utf8_to_string)
+                .unwrap_or(default_form.frames),
+            ..Default::default()
+        }
+    }
+
+    /// Builds the query string for this search, leaving out fields that match the default
+    fn to_query(&self) -> String {
+        let default_form = Form::default();
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+
+        for (name, value, default) in [
+            ("imagelink", &self.link, &default_form.link),
+            ("hash", &self.hash, &default_form.hash),
+            ("distance", &self.distance, &default_form.distance),
+            (
+                "wide_distance",
+                &self.wide_distance,
+                &default_form.wide_distance,
+            ),
+            ("nsfw", &self.nsfw, &default_form.nsfw),
+            ("subreddits", &self.subreddits, &default_form.subreddits),
+            ("authors", &self.authors, &default_form.authors),
+            ("before", &self.before, &default_form.before),
+            ("after", &self.after, &default_form.after),
+            ("min_score", &self.min_score, &default_form.min_score),
+            ("spoiler", &self.spoiler, &default_form.spoiler),
+            ("video", &self.video, &default_form.video),
+            ("cursor", &self.cursor, &default_form.cursor),
+            ("group", &self.group, &default_form.group),
+            ("agree", &self.agree, &default_form.agree),
+            ("transforms", &self.transforms, &default_form.transforms),
+            ("frames", &self.frames, &default_form.frames),
+        ] {
+            if value != default {
+                query.append_pair(name, value);
+            }
+        }
+
+        query.finish()
+    }
+}
+
 #[derive(Debug, Serialize)]
-struct Search {
+pub struct Search {
     form: Form,
     default_form: Form,
-    findings: Option<Findings>,
-    error: Option<UserError>,
+    pub findings: Option<Findings>,
+    pub error: Option<UserError>,
+    next_page: Option<String>,
     upload: bool,
     max_distance: u8,
-    ingest_state: Option<IngestState>,
+    pub ingest_state: Option<IngestState>,
 }
 
-impl Search {
-    async fn default() -> Search {
-        let state_string = tokio::fs::read_to_string(&CONFIG.state_file).await;
-        let state = match state_string {
+/// Whether the ingester wrote its state recently enough for it to still be running
+pub fn ingest_state_fresh(state: &IngestState) -> bool {
+    (Utc::now().naive_utc() - state.as_of) < Duration::minutes(2)
+}
+
+/// The ingest state file as last written, however old
+pub async fn read_ingest_state_file() -> Option<IngestState> {
+    let state_string = tokio::fs::read_to_string(&CONFIG.state_file).await;
+    match state_string {
+        Err(e) => {
+            warn!("Error reading ingest state file: {}", e);
+            None
+        }
+        Ok(s) => match ron::from_str::<IngestState>(&s) {
             Err(e) => {
-                warn!("Error reading ingest state file: {}", e);
+                warn!("Error parsing ingest state file: {}", e);
                 None
             }
-            Ok(s) => match ron::from_str::<IngestState>(&s) {
-                Err(e) => {
-                    warn!("Error parsing ingest state file: {}", e);
-                    None
-                }
-                Ok(s) => {
-                    if (Utc::now().naive_utc() - s.as_of) < Duration::minutes(2) {
-                        Some(s)
-                    } else {
-                        None
-                    }
-                }
-            },
-        };
+            Ok(s) => Some(s),
+        },
+    }
+}
 
+pub async fn read_ingest_state() -> Option<IngestState> {
+    read_ingest_state_file().await.filter(ingest_state_fresh)
+}
+
+impl Search {
+    async fn default() -> Search {
         Search {
             form: Form::default(),
             default_form: Form::default(),
             findings: None,
             error: None,
+            next_page: None,
             upload: false,
             max_distance: CONFIG.max_distance,
-            ingest_state: state,
+            ingest_state: read_ingest_state().await,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct Params {
+    hash: Option<Hashes>,
     distance: i64,
+    /// Between 256-bit hashes, which candidates within `distance` must also be within.
+    /// Defaults to `distance` scaled up to the wider hash.
+    wide_distance: Option<i64>,
     nsfw: NSFWOption,
-    subreddits: Vec<String>,
-    authors: Vec<String>,
+    subreddits: NameFilter,
+    authors: NameFilter,
+    before: Option<chrono::NaiveDateTime>,
+    after: Option<chrono::NaiveDateTime>,
+    min_score: Option<i64>,
+    spoiler: FlagOption,
+    video: FlagOption,
+    cursor: Option<Cursor>,
+    group: bool,
+    /// Other algorithms whose hashes must also be within the distance
+    agree: Vec<&'static dyn HashAlgorithm>,
+    /// Whether to also search for the query flipped and rotated
+    transforms: bool,
+    /// Whether to also search the sampled frames of animations, the query's and the index's
+    frames: bool,
 }
 
 impl Params {
     pub fn from_form(form: &Form) -> Result<Params, UserError> {
-        Ok(Params {
+        let params = Params {
+            hash: if form.hash.is_empty() {
+                None
+            } else {
+                Some(
+                    form.hash
+                        .parse()
+                        .map_err(map_ue!("invalid hash parameter", Source::User))?,
+                )
+            },
             distance: {
                 let distance = if form.distance.is_empty() {
                     1
@@ -164,77 +630,269 @@ impl Params {
 
                 distance as i64
             },
+            wide_distance: if form.wide_distance.is_empty() {
+                None
+            } else {
+                let wide_distance: u16 = form
+                    .wide_distance
+                    .parse()
+                    .map_err(map_ue!("invalid wide_distance parameter", Source::User))?;
+
+                if wide_distance > 256 {
+                    return Err(ue!("wide distance too large", Source::User));
+                }
+
+                Some(wide_distance as i64)
+            },
             nsfw: form
                 .nsfw
                 .parse()
                 .map_err(map_ue!("invalid nsfw parameter", Source::User))?,
             subreddits: form
                 .subreddits
-                .split_whitespace()
-                .map(str::to_lowercase)
-                .collect(),
+                .parse()
+                .map_err(map_ue!("invalid subreddits parameter", Source::User))?,
             authors: form
                 .authors
-                .split_whitespace()
-                .map(str::to_lowercase)
-                .collect(),
-        })
+                .parse()
+                .map_err(map_ue!("invalid authors parameter", Source::User))?,
+            before: parse_date(&form.before)
+                .map_err(map_ue!("invalid before parameter", Source::User))?,
+            after: parse_date(&form.after)
+                .map_err(map_ue!("invalid after parameter", Source::User))?,
+            min_score: if form.min_score.is_empty() {
+                None
+            } else {
+                Some(
+                    form.min_score
+                        .parse()
+                        .map_err(map_ue!("invalid min_score parameter", Source::User))?,
+                )
+            },
+            spoiler: form
+                .spoiler
+                .parse()
+                .map_err(map_ue!("invalid spoiler parameter", Source::User))?,
+            video: form
+                .video
+                .parse()
+                .map_err(map_ue!("invalid video parameter", Source::User))?,
+            cursor: if form.cursor.is_empty() {
+                None
+            } else {
+                Some(
+                    form.cursor
+                        .parse()
+                        .map_err(map_ue!("invalid cursor parameter", Source::User))?,
+                )
+            },
+            group: match form.group.as_str() {
+                "" | "false" => false,
+                "true" => true,
+                _ => return Err(ue!("invalid group parameter", Source::User)),
+            },
+            agree: form
+                .agree
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty() && *name != DHash.name())
+                .map(|name| {
+                    algorithm(name).ok_or_else(|| ue!("invalid agree parameter", Source::User))
+                })
+                .collect::<Result<_, _>>()?,
+            transforms: match form.transforms.as_str() {
+                "" | "false" => false,
+                "true" => true,
+                _ => return Err(ue!("invalid transforms parameter", Source::User)),
+            },
+            frames: match form.frames.as_str() {
+                "" | "false" => false,
+                "true" => true,
+                _ => return Err(ue!("invalid frames parameter", Source::User)),
+            },
+        };
+
+        // Only the query itself has the other algorithms' hashes to agree with
+        if (params.transforms || params.frames) && !params.agree.is_empty() {
+            return Err(ue!(
+                "agree can't be used with flips, rotations or frames",
+                Source::User
+            ));
+        }
+
+        Ok(params)
     }
 }
 
-async fn make_findings(hash: Hash, params: Params) -> Result<Findings, UserError> {
-    macro_rules! tosql {
-        ($v:expr) => {
-            (&$v as &(dyn tokio_postgres::types::ToSql + Sync))
-        };
+async fn make_findings(hashes: Hashes, params: Params) -> Result<Findings, UserError> {
+    // Loaded before taking a connection, since loading it may need one of its own
+    let denylist = denylist().await?;
+    let client = PG_POOL.get().await?;
+
+    let mut query = QueryBuilder::new();
+
+    let distance_arg = query.arg(params.distance);
+    let limit_arg = query.arg(CONFIG.max_results);
+
+    // Each hash to search for, with the transform of the query it's the hash of, or whether
+    // it's a frame of the query. Those only have a dHash, so they're ranked by it alone.
+    let mut query_hashes = vec![format!(
+        "(NULL::varchar, {}::bigint, {}::bit(256), {}::bigint, false)",
+        query.arg(hashes.dhash),
+        query.arg(hashes.wide),
+        query.arg(hashes.trimmed)
+    )];
+    if params.transforms {
+        if hashes.transforms.is_empty() {
+            return Err(ue!(
+                "flips and rotations can only be searched for with an image, not a hash",
+                Source::User
+            ));
+        }
+
+        for (transform, hash) in &hashes.transforms {
+            query_hashes.push(format!(
+                "({}, {}, NULL, NULL, false)",
+                query.arg(transform.name()),
+                query.arg(*hash)
+            ));
+        }
+    }
+    // A hash given directly may have no frames, but is still looked for among the index's
+    if params.frames {
+        for (_, hash) in hashes.frames.iter().flatten() {
+            query_hashes.push(format!("(NULL, {}, NULL, NULL, true)", query.arg(*hash)));
+        }
     }
 
-    let client = PG_POOL.get().await?;
+    // Images are found by either their hash or the one with borders trimmed, whichever is
+    // closer. The wide hash is of the untrimmed image, so only applies to the first.
+    let raw_distance = "hash <-> query_hash";
+    let trimmed_distance = "images.trimmed_hash <-> query_trimmed_hash";
+    let trimmed = format!("COALESCE({} < {}, false)", trimmed_distance, raw_distance);
+    let distance = format!("LEAST({}, {})", raw_distance, trimmed_distance);
 
-    let (s_query, a_query, args) = if params.subreddits.is_empty() && params.authors.is_empty() {
+    // Candidates from frames are found through their own index, so they're unioned with the
+    // images' rather than joined on a condition that would keep either index from being used
+    let (candidates, distance, trimmed, frame) = if params.frames {
+        let frame_distance = "(SELECT MIN(image_frames.hash <-> query_hash) FROM image_frames \
+                              WHERE image_frames.image_id = images.id)";
+        let frame_found = format!("COALESCE({} < {}, false)", frame_distance, distance);
         (
-            "",
-            "",
-            vec![
-                tosql!(hash),
-                tosql!(params.distance),
-                tosql!(CONFIG.max_results),
-            ],
+            format!(
+                "INNER JOIN LATERAL (\
+                 SELECT id FROM images WHERE hash <@ (query_hash, {distance_arg}) \
+                 OR trimmed_hash <@ (query_trimmed_hash, {distance_arg}) \
+                 UNION SELECT image_id FROM image_frames \
+                 WHERE image_frames.hash <@ (query_hash, {distance_arg})\
+                 ) AS candidates (candidate_id) ON true \
+                 INNER JOIN images ON images.id = candidate_id",
+                distance_arg = distance_arg
+            ),
+            format!("LEAST({}, {})", distance, frame_distance),
+            format!("(NOT {} AND {})", frame_found, trimmed),
+            format!("(query_is_frame OR {})", frame_found),
         )
-    } else if params.authors.is_empty() {
+    } else {
         (
-            "AND LOWER(subreddit) = ANY($4)",
-            "",
-            vec![
-                tosql!(hash),
-                tosql!(params.distance),
-                tosql!(CONFIG.max_results),
-                tosql!(params.subreddits),
-            ],
+            format!(
+                "INNER JOIN images ON (hash <@ (query_hash, {distance_arg}) \
+                 OR trimmed_hash <@ (query_trimmed_hash, {distance_arg}))",
+                distance_arg = distance_arg
+            ),
+            distance,
+            trimmed,
+            "false".to_string(),
         )
-    } else if params.subreddits.is_empty() {
+    };
+
+    // The 64-bit hash finds candidates through the index, then the 256-bit one ranks and
+    // filters them. Images without a wide hash rank by their 64-bit distance, scaled up.
+    let wide_distance = format!(
+        "CASE WHEN {} OR {} THEN NULL ELSE bit_count(images.wide_hash # query_wide_hash) END",
+        trimmed, frame
+    );
+    let rank = format!("COALESCE({}, {} * {})", wide_distance, WIDE_SCALE, distance);
+    let wide_distance_arg = query.arg(params.wide_distance.unwrap_or(params.distance * WIDE_SCALE));
+    query.and(format!(
+        "COALESCE({} <= {}, true)",
+        wide_distance, wide_distance_arg
+    ));
+
+    // Images hashed before an algorithm was added have no hash for it, so never agree
+    for algorithm in &params.agree {
+        let hash = hashes.get(*algorithm).ok_or_else(|| {
+            ue!(
+                format!("no {} known for this image to agree with", algorithm.name()),
+                Source::User
+            )
+        })?;
+        let algorithm_arg = query.arg(hash);
+        query.and(format!(
+            "images.{} <-> {} <= {}",
+            algorithm.column(),
+            algorithm_arg,
+            distance_arg
+        ));
+    }
+
+    match params.nsfw {
+        NSFWOption::Only => {
+            query.and("nsfw = true");
+        }
+        NSFWOption::Allow => {}
+        NSFWOption::Never => {
+            query.and("nsfw = false");
+        }
+    }
+
+    params.subreddits.apply("subreddit", &mut query);
+    params.authors.apply("author", &mut query);
+
+    query
+        .and("NOT EXISTS (SELECT FROM takedowns WHERE takedowns.post_id = posts.id)")
+        .and("NOT EXISTS (SELECT FROM takedowns WHERE takedowns.image_id = images.id)")
+        .and(NOT_DENIED);
+
+    if let Some(condition) = params.spoiler.condition("spoiler") {
+        query.and(condition);
+    }
+    if let Some(condition) = params.video.condition("is_video") {
+        query.and(condition);
+    }
+
+    if let Some(after) = params.after {
+        let after_arg = query.arg(after);
+        query.and(format!("created_utc >= {}", after_arg));
+    }
+    if let Some(before) = params.before {
+        let before_arg = query.arg(before);
+        query.and(format!("created_utc < {}", before_arg));
+    }
+    if let Some(min_score) = params.min_score {
+        let min_score_arg = query.arg(min_score);
+        query.and(format!("score >= {}", min_score_arg));
+    }
+
+    // The cursor applies after a post found through several hashes is narrowed to its best
+    let after_cursor = match params.cursor {
+        Some(cursor) => format!(
+            " WHERE (rank, distance, created_utc, post_id) > ({}, {}, {}, {})",
+            query.arg(cursor.rank),
+            query.arg(cursor.distance),
+            query.arg(cursor.created_utc),
+            query.arg(cursor.id),
+        ),
+        None => String::new(),
+    };
+
+    let (distinct, distinct_order) = if query_hashes.len() > 1 {
         (
-            "",
-            "AND LOWER(author) = ANY($4)",
-            vec![
-                tosql!(hash),
-                tosql!(params.distance),
-                tosql!(CONFIG.max_results),
-                tosql!(params.authors),
-            ],
+            "DISTINCT ON (posts.id) ",
+            " ORDER BY posts.id, rank, distance, query_is_frame",
         )
     } else {
-        (
-            "AND LOWER(subreddit) = ANY($4)",
-            "AND LOWER(author) = ANY($5)",
-            vec![
-                tosql!(hash),
-                tosql!(params.distance),
-                tosql!(CONFIG.max_results),
-                tosql!(params.subreddits),
-                tosql!(params.authors),
-            ],
-        )
+        ("", "")
     };
 
     let search_start = Instant::now();
@@ -242,113 +900,251 @@ async fn make_findings(hash: Hash, params: Params) -> Result<Findings, UserError
     let rows = client
         .query(
             format!(
-                "SELECT hash <-> $1 as distance, preview, images.link as link, permalink, \
-                 score, author, created_utc, subreddit, title \
-                 FROM posts INNER JOIN images \
-                 ON hash <@ ($1, $2) \
-                 AND image_id = images.id \
-                 {} \
-                 {} \
-                 {} \
-                 ORDER BY distance ASC, created_utc ASC LIMIT $3",
-                match params.nsfw {
-                    NSFWOption::Only => "AND nsfw = true",
-                    NSFWOption::Allow => "",
-                    NSFWOption::Never => "AND nsfw = false",
-                },
-                s_query,
-                a_query,
+                "SELECT * FROM (\
+                 SELECT {distinct}transform, {distance} as distance, {trimmed} as trimmed, \
+                 {frame} as frame, {rank} as rank, {wide_distance} as wide_distance, \
+                 posts.id as post_id, preview, \
+                 images.link as link, permalink, \
+                 score, author, created_utc, subreddit, title, \
+                 images.id as image_id, images.hash as image_hash, \
+                 reddit_id_int, crosspost_parent \
+                 FROM (VALUES {query_hashes}) \
+                 AS query_hashes \
+                 (transform, query_hash, query_wide_hash, query_trimmed_hash, query_is_frame) \
+                 {candidates} \
+                 INNER JOIN posts ON image_id = images.id\
+                 {conditions}{distinct_order}) matches{after_cursor} \
+                 ORDER BY rank ASC, distance ASC, created_utc ASC, post_id ASC LIMIT {limit_arg}",
+                query_hashes = query_hashes.join(", "),
+                candidates = candidates,
+                conditions = query.conditions(),
+                limit_arg = limit_arg,
+                distance = distance,
+                trimmed = trimmed,
+                frame = frame,
+                rank = rank,
+                wide_distance = wide_distance,
+                distinct = distinct,
+                distinct_order = distinct_order,
+                after_cursor = after_cursor,
             )
             .as_str(),
-            &args,
+            &query.args(),
         )
         .await
-        .map_err(|e| {
-            if let Some(dberror) = e.source().and_then(|e| e.downcast_ref::<DbError>()) {
-                if *dberror.code() == SqlState::QUERY_CANCELED
-                    && dberror.message() == "canceling statement due to statement timeout"
-                {
-                    ue!("query took too long", Source::User)
-                } else {
-                    e.into()
-                }
-            } else {
-                e.into()
-            }
-        })?;
+        .map_err(sql::timed_out)?;
 
     let search_took = search_start.elapsed();
 
+    metrics::SEARCH_SECONDS
+        .with_label_values(&["query"])
+        .observe(search_took.as_secs_f64());
+
+    // A full page means there may be more matches after it
+    let next_cursor = if rows.len() as i64 >= CONFIG.max_results {
+        rows.last().map(|row| {
+            Cursor {
+                rank: row.get("rank"),
+                distance: row.get("distance"),
+                created_utc: row.get("created_utc"),
+                id: row.get("post_id"),
+            }
+            .to_string()
+        })
+    } else {
+        None
+    };
+
+    // The file's denied images are hidden after paging so the cursor still marks where the
+    // query stopped. The table's were left out by the query itself.
+    let matches = rows
+        .iter()
+        .filter(|row| {
+            denylist
+                .matches_file(Hash(row.get::<_, i64>("image_hash") as u64))
+                .is_none()
+        })
+        .map(move |row| {
+            let link: String = row.get("link");
+            let preview = row
+                .get::<_, Option<String>>("preview")
+                .map(|p| Submission::unescape(&p))
+                .unwrap_or_else(|| link.clone());
+
+            Match {
+                permalink: format!("https://reddit.com{}", row.get::<_, &str>("permalink")),
+                distance: row.get("distance"),
+                wide_distance: row.get("wide_distance"),
+                transform: row.get("transform"),
+                trimmed: row.get("trimmed"),
+                frame: row.get("frame"),
+                score: row.get("score"),
+                author: row.get("author"),
+                link,
+                preview,
+                created_utc: row.get("created_utc"),
+                subreddit: row.get("subreddit"),
+                title: row.get("title"),
+                image_id: row.get("image_id"),
+                reddit_id_int: row.get("reddit_id_int"),
+                crosspost_parent: row.get("crosspost_parent"),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let (matches, groups) = if params.group {
+        (Vec::new(), Some(group_matches(matches)))
+    } else {
+        (matches, None)
+    };
+
     Ok(Findings {
         took: format!(
             "{}.{:03}",
             search_took.as_secs(),
             search_took.subsec_millis()
         ),
-        matches: rows
-            .iter()
-            .map(move |row| {
-                let link: String = row.get("link");
-                let preview = row
-                    .get::<_, Option<String>>("preview")
-                    .map(|p| Submission::unescape(&p))
-                    .unwrap_or_else(|| link.clone());
-
-                Match {
-                    permalink: format!("https://reddit.com{}", row.get::<_, &str>("permalink")),
-                    distance: row.get("distance"),
-                    score: row.get("score"),
-                    author: row.get("author"),
-                    link,
-                    preview,
-                    created_utc: row.get("created_utc"),
-                    subreddit: row.get("subreddit"),
-                    title: row.get("title"),
-                }
-            })
-            .collect(),
+        matches,
+        groups,
+        next_cursor,
     })
 }
 
-async fn get_search(qs: SearchQuery) -> Search {
-    let imagelink = qs.imagelink.clone();
+/// The hashes of a post's image, and the link to it. The post is looked up by its ID or by the
+/// permalink it was linked by, if any.
+async fn reddit_post_hash(
+    reddit_id: &str,
+    permalink: Option<String>,
+) -> Result<(Hashes, String), UserError> {
+    let client = PG_POOL.get().await?;
 
+    let row = client
+        .query_opt(
+            format!(
+                "SELECT images.link, {} FROM posts INNER JOIN images \
+                 ON image_id = images.id \
+                 WHERE reddit_id = $1 OR permalink = $2 \
+                 LIMIT 1",
+                Hashes::columns()
+            )
+            .as_str(),
+            &[&reddit_id, &permalink],
+        )
+        .await?;
+
+    drop(client);
+
+    if let Some(row) = row {
+        return Ok((Hashes::from_row(&row), row.get("link")));
+    }
+
+    let post = get_submission(reddit_id)
+        .await?
+        .ok_or_else(|| ue!("Reddit post not found", Source::User))?;
+
+    if !post.desirable() {
+        return Err(ue!("Reddit post doesn't link to an image", Source::User));
+    }
+
+    let image_link = post.choose_url()?.to_string();
+
+    // Ingest wouldn't have saved a post of a banned image, so don't fetch it for one either
+    if ban_list().await?.find(&image_link).is_some() {
+        return Err(ue!("Reddit post links to a banned image", Source::User));
+    }
+
+    let hashes = save_hash(&image_link, HashDest::ImageCache, FetchPolicy::Public)
+        .await?
+        .hashes;
+
+    Ok((hashes, image_link))
+}
+
+/// Hashes the image a link points to, using the post's image if it's a link to a Reddit post.
+/// Hashes of flips, rotations and frames are only computed when asked for, and aren't stored
+/// alongside the rest, so the image is downloaded again for them.
+async fn link_hash(link: &str, transforms: bool, frames: bool) -> Result<Hashes, UserError> {
+    let (hashes, image_link) = match reddit_post_id(link) {
+        Some(reddit_id) => reddit_post_hash(&reddit_id, reddit_permalink(link)).await?,
+        None => {
+            Url::parse(link).map_err(map_ue!("invalid URL"))?;
+            let hashes = save_hash(link, HashDest::ImageCache, FetchPolicy::Public)
+                .await?
+                .hashes;
+            (hashes, link.to_string())
+        }
+    };
+
+    if transforms || frames {
+        refetch_hash(&image_link, FetchPolicy::Public, transforms, frames).await
+    } else {
+        Ok(hashes)
+    }
+}
+
+pub async fn get_search(qs: SearchQuery) -> Search {
     let default_form = Form::default();
     let form = Form {
         distance: qs.distance.unwrap_or(default_form.distance),
+        wide_distance: qs.wide_distance.unwrap_or(default_form.wide_distance),
         nsfw: qs.nsfw.unwrap_or(default_form.nsfw),
         subreddits: qs.subreddits.unwrap_or(default_form.subreddits),
         authors: qs.authors.unwrap_or(default_form.authors),
+        before: qs.before.unwrap_or(default_form.before),
+        after: qs.after.unwrap_or(default_form.after),
+        min_score: qs.min_score.unwrap_or(default_form.min_score),
+        spoiler: qs.spoiler.unwrap_or(default_form.spoiler),
+        video: qs.video.unwrap_or(default_form.video),
+        cursor: qs.cursor.unwrap_or(default_form.cursor),
         link: qs.imagelink.unwrap_or(default_form.link),
+        hash: qs.hash.unwrap_or(default_form.hash),
+        group: qs.group.unwrap_or(default_form.group),
+        agree: qs.agree.unwrap_or(default_form.agree),
+        transforms: qs.transforms.unwrap_or(default_form.transforms),
+        frames: qs.frames.unwrap_or(default_form.frames),
     };
 
     let err_form = form.clone();
 
-    let findings = match imagelink {
-        None => Ok(None),
-        Some(link) => {
-            if &link != "" {
-                match Url::parse(&link).map_err(map_ue!("invalid URL")) {
-                    Ok(_url) => match Params::from_form(&form) {
-                        Ok(params) => {
-                            save_hash(&link, HashDest::ImageCache)
-                                .and_then(|hash_saved| async move {
-                                    make_findings(hash_saved.hash, params).await.map(Some)
-                                })
-                                .await
-                        }
-                        Err(e) => Err(e),
-                    },
-                    Err(e) => Err(e),
+    let search_start = Instant::now();
+
+    let findings = if form.link.is_empty() && form.hash.is_empty() {
+        Ok(None)
+    } else {
+        match Params::from_form(&form) {
+            Ok(params) => match params.hash.clone() {
+                // A hash given directly doesn't need an image to be fetched
+                Some(hashes) => make_findings(hashes, params).await.map(Some),
+                None => {
+                    link_hash(&form.link, params.transforms, params.frames)
+                        .and_then(
+                            |hashes| async move { make_findings(hashes, params).await.map(Some) },
+                        )
+                        .await
                 }
-            } else {
-                Ok(None)
-            }
+            },
+            Err(e) => Err(e),
         }
     };
 
+    if !matches!(findings, Ok(None)) {
+        metrics::SEARCH_SECONDS
+            .with_label_values(&["total"])
+            .observe(search_start.elapsed().as_secs_f64());
+    }
+
     match findings {
         Ok(findings) => Search {
+            next_page: findings
+                .as_ref()
+                .and_then(|findings| findings.next_cursor.as_ref())
+                .map(|cursor| {
+                    Form {
+                        cursor: cursor.clone(),
+                        ..form.clone()
+                    }
+                    .to_query()
+                }),
             form,
             error: None,
             findings,
@@ -365,68 +1161,263 @@ async fn get_search(qs: SearchQuery) -> Search {
     }
 }
 
-async fn post_search(mut form: FormData) -> Search {
-    #[allow(clippy::ptr_arg)]
-    fn utf8_to_string(utf8: &Vec<u8>) -> String {
-        String::from_utf8_lossy(utf8.as_slice()).to_string()
-    }
+struct Part {
+    name: String,
+    filename: Option<String>,
+    data: Vec<u8>,
+}
 
-    let do_findings = move || async move {
-        let mut map: HashMap<String, Vec<u8>> = HashMap::new();
+/// Reads every part of a form, failing as soon as one is more than `max_part` bytes or they
+/// add up to more than `max_length`
+async fn read_parts(
+    mut form: FormData,
+    max_length: u64,
+    max_part: u64,
+) -> Result<Vec<Part>, UserError> {
+    let mut parts = Vec::new();
+    let mut length = 0;
 
-        while let Some(mut part) = form.try_next().await? {
-            let name = part.name().to_string();
-            let mut data = Vec::<u8>::new();
+    while let Some(mut part) = form.try_next().await? {
+        let name = part.name().to_string();
+        let filename = part.filename().map(String::from);
+        let mut data = Vec::<u8>::new();
 
-            while let Some(b) = part.data().await {
-                b?.reader().read_to_end(&mut data)?;
+        while let Some(b) = part.data().await {
+            let b = b?;
+            length += b.remaining() as u64;
+            if length > max_length {
+                return Err(ue!(
+                    format!("upload too large, the limit is {} bytes", max_length),
+                    Source::User
+                ));
             }
+            if (data.len() + b.remaining()) as u64 > max_part {
+                return Err(ue!(
+                    format!("{} too large, the limit is {} bytes", name, max_part),
+                    Source::User
+                ));
+            }
+            b.reader().read_to_end(&mut data)?;
+        }
+
+        parts.push(Part {
+            name,
+            filename,
+            data,
+        });
+    }
+
+    Ok(parts)
+}
+
+/// One image in a batch search
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchInput {
+    Link(String),
+    File {
+        name: Option<String>,
+        #[serde(skip)]
+        data: Vec<u8>,
+    },
+    Hash(String),
+}
 
-            map.insert(name, data);
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    input: BatchInput,
+    findings: Option<Findings>,
+    error: Option<UserError>,
+}
+
+async fn batch_findings(input: &mut BatchInput, params: Params) -> Result<Findings, UserError> {
+    let hashes = match input {
+        BatchInput::Link(link) => link_hash(link, params.transforms, params.frames).await?,
+        BatchInput::File { data, .. } => {
+            spawn_hashes_from_memory(std::mem::take(data), params.transforms, params.frames).await?
         }
+        BatchInput::Hash(hash) => hash
+            .parse()
+            .map_err(map_ue!("invalid hash", Source::User))?,
+    };
 
-        let default_form = Form::default();
-        let form = Form {
-            distance: map
-                .get("distance")
-                .map(utf8_to_string)
-                .unwrap_or(default_form.distance),
-            nsfw: map
-                .get("nsfw")
-                .map(utf8_to_string)
-                .unwrap_or(default_form.nsfw),
-            subreddits: map
-                .get("subreddits")
-                .map(utf8_to_string)
-                .unwrap_or(default_form.subreddits),
-            authors: map
-                .get("authors")
-                .map(utf8_to_string)
-                .unwrap_or(default_form.authors),
-            ..Default::default()
-        };
+    make_findings(hashes, params).await
+}
+
+/// Every image in a batch search, with the filters applied to each
+pub struct Batch {
+    inputs: Vec<BatchInput>,
+    params: Params,
+}
+
+/// Reads the links, files and hashes to search for from a form.
+/// Links may be given one per part or several per part on separate lines.
+pub async fn read_batch(form: FormData) -> Result<Batch, UserError> {
+    let mut inputs = Vec::new();
+    let mut map = HashMap::new();
+
+    // No more than every input could need, each at most the size of a single search's upload
+    let max_upload = CONFIG.rate_limits.max_upload;
+    let max_length = CONFIG
+        .batch_limits
+        .max_length
+        .min(CONFIG.batch_limits.max_inputs as u64 * max_upload);
+
+    for part in read_parts(form, max_length, max_upload).await? {
+        match part.name.as_str() {
+            "imagelink" | "hash" => {
+                for line in String::from_utf8_lossy(&part.data).lines() {
+                    let line = line.trim();
+                    if !line.is_empty() {
+                        inputs.push(if part.name == "hash" {
+                            BatchInput::Hash(line.to_string())
+                        } else {
+                            BatchInput::Link(line.to_string())
+                        });
+                    }
+                }
+            }
+            "imagefile" => inputs.push(BatchInput::File {
+                name: part.filename,
+                data: part.data,
+            }),
+            _ => {
+                map.insert(part.name, part.data);
+            }
+        }
+    }
+
+    if inputs.is_empty() {
+        return Err(ue!("no images provided", Source::User));
+    }
+    if inputs.len() > CONFIG.batch_limits.max_inputs {
+        return Err(ue!(
+            format!(
+                "too many images, the limit is {}",
+                CONFIG.batch_limits.max_inputs
+            ),
+            Source::User
+        ));
+    }
+
+    // Each input gets its own results, so a cursor from another search can't apply
+    let form = Form {
+        cursor: String::new(),
+        ..Form::from_map(&map)
+    };
+    let params = Params::from_form(&form)?;
+
+    Ok(Batch { inputs, params })
+}
+
+impl Batch {
+    /// Searches for every image, in order, each taking a token from the client's bucket as it's
+    /// started. The first's was taken before the batch was read. Once the bucket runs out, the
+    /// rest fail with how long until they can be retried, so batches larger than the bucket
+    /// can be sent again in parts.
+    pub async fn search(self, client: &Client) -> Vec<BatchResult> {
+        let Batch { inputs, params } = self;
+
+        futures::stream::iter(inputs.into_iter().enumerate())
+            .map(|(index, mut input)| {
+                let params = params.clone();
+                async move {
+                    let taken = if index == 0 {
+                        Ok(())
+                    } else {
+                        limit::take(client, 1)
+                            .await
+                            .map_err(|refused| refused.user_error())
+                    };
+
+                    let found = match taken {
+                        Ok(()) => batch_findings(&mut input, params).await,
+                        Err(error) => Err(error),
+                    };
+
+                    let (findings, error) = match found {
+                        Ok(findings) => (Some(findings), None),
+                        Err(error) => (None, Some(error)),
+                    };
+
+                    BatchResult {
+                        input,
+                        findings,
+                        error,
+                    }
+                }
+            })
+            .buffered(CONFIG.batch_limits.concurrency)
+            .collect()
+            .await
+    }
+}
 
-        let hash = map
-            .get("imagefile")
-            .map(|bytes| hash_from_memory(bytes))
-            .transpose()?;
+pub async fn post_search(form: FormData) -> Search {
+    let do_findings = move || async move {
+        let mut map = read_parts(
+            form,
+            CONFIG.rate_limits.max_upload,
+            CONFIG.rate_limits.max_upload,
+        )
+        .await?
+        .into_iter()
+        .map(|part| (part.name, part.data))
+        .collect::<HashMap<_, _>>();
+
+        let form = Form::from_map(&map);
 
         let params = Params::from_form(&form)?;
 
-        Ok(match hash {
+        let hashes = match map.remove("imagefile") {
+            Some(bytes) => {
+                Some(spawn_hashes_from_memory(bytes, params.transforms, params.frames).await?)
+            }
+            None => params.hash.clone(),
+        };
+
+        Ok(match hashes {
             None => (form, None),
-            Some(hash) => (form, Some(make_findings(hash, params).await?)),
+            Some(hashes) => (
+                // Later pages are searched by hash so the upload isn't needed again, keeping
+                // only the hashes this search uses so the links stay short
+                Form {
+                    hash: hashes
+                        .narrowed(&params.agree, params.transforms, params.frames)
+                        .to_string(),
+                    ..form
+                },
+                Some(make_findings(hashes, params).await?),
+            ),
         })
     };
 
+    let search_start = Instant::now();
+
     let output = do_findings().await;
 
+    if !matches!(output, Ok((_, None))) {
+        metrics::SEARCH_SECONDS
+            .with_label_values(&["total"])
+            .observe(search_start.elapsed().as_secs_f64());
+    }
+
     let (form, findings, error) = match output {
         Ok((form, findings)) => (form, findings, None),
         Err(error) => (Form::default(), None, Some(error)),
     };
 
     Search {
+        next_page: findings
+            .as_ref()
+            .and_then(|findings| findings.next_cursor.as_ref())
+            .map(|cursor| {
+                Form {
+                    cursor: cursor.clone(),
+                    ..form.clone()
+                }
+                .to_query()
+            }),
         form,
         error,
         findings,
@@ -485,11 +1476,155 @@ pub async fn post_response(form: FormData) -> impl warp::Reply {
                 })
                 .unwrap_or(StatusCode::OK),
         ),
-        Err(_) => (
-            "<h1>Error 500: Internal Server Error</h1>".to_string(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ),
+        Err(e) => {
+            error!("{}", e);
+            (
+                "<h1>Error 500: Internal Server Error</h1>".to_string(),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        }
     };
 
     warp::reply::with_status(warp::reply::html(page), status)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = Cursor {
+            rank: 2,
+            distance: 3,
+            created_utc: chrono::NaiveDateTime::from_timestamp_opt(1_500_000_000, 0).unwrap(),
+            id: 42,
+        };
+        let s = cursor.to_string();
+        assert_eq!(s, "2_3_1500000000_42");
+        assert_eq!(s.parse::<Cursor>().unwrap(), cursor);
+    }
+
+    #[test]
+    fn cursor_malformed() {
+        for s in &[
+            "",
+            "1_2_3",
+            "1_2_3_x",
+            "a_2_3_4",
+            "1_2_3_4_5",
+            "1_2_99999999999999999_4",
+        ] {
+            assert!(s.parse::<Cursor>().is_err(), "{:?} parsed", s);
+        }
+    }
+
+    #[test]
+    fn name_filter_parse() {
+        let filter = "Pics -funny art* -meme*".parse::<NameFilter>().unwrap();
+        assert_eq!(filter.include, vec!["pics"]);
+        assert_eq!(filter.exclude, vec!["funny"]);
+        assert_eq!(filter.include_prefixes, vec!["art"]);
+        assert_eq!(filter.exclude_prefixes, vec!["meme"]);
+
+        for s in &["-", "*", "-*", "a*b", "a**"] {
+            assert!(s.parse::<NameFilter>().is_err(), "{:?} parsed", s);
+        }
+    }
+
+    #[test]
+    fn name_filter_like_escaping() {
+        assert_eq!(NameFilter::like_prefix("art"), "art%");
+        assert_eq!(NameFilter::like_prefix("a_b%c\\d"), "a\\_b\\%c\\\\d%");
+    }
+
+    #[test]
+    fn name_filter_conditions() {
+        let mut query = QueryBuilder::new();
+        "pics art* -funny -meme*"
+            .parse::<NameFilter>()
+            .unwrap()
+            .apply("subreddit", &mut query);
+        assert_eq!(
+            query.conditions(),
+            " AND (LOWER(subreddit) = ANY($1) OR LOWER(subreddit) LIKE ANY($2)) \
+             AND LOWER(subreddit) <> ALL($3) \
+             AND LOWER(subreddit) NOT LIKE ALL($4)"
+        );
+        assert_eq!(query.args().len(), 4);
+    }
+
+    fn post(
+        reddit_id_int: i64,
+        image_id: i64,
+        crosspost_parent: Option<i64>,
+        created: i64,
+    ) -> Match {
+        Match {
+            author: None,
+            created_utc: chrono::NaiveDateTime::from_timestamp_opt(created, 0).unwrap(),
+            distance: 0,
+            wide_distance: None,
+            transform: None,
+            trimmed: false,
+            frame: false,
+            link: String::new(),
+            preview: String::new(),
+            permalink: String::new(),
+            score: 0,
+            subreddit: String::new(),
+            title: String::new(),
+            image_id,
+            reddit_id_int,
+            crosspost_parent,
+        }
+    }
+
+    #[test]
+    fn group_crossposts_and_reposts() {
+        let groups = group_matches(vec![
+            // A crosspost of a crosspost, each with its own image row
+            post(3, 30, Some(2), 300),
+            post(9, 90, None, 50),
+            post(2, 20, Some(1), 200),
+            // An exact repost of the crossposted image, unrelated by crossposts
+            post(4, 30, None, 400),
+            post(1, 10, None, 100),
+        ]);
+
+        assert_eq!(groups.len(), 2);
+
+        assert_eq!(groups[0].count, 4);
+        assert_eq!(groups[0].earliest.reddit_id_int, 1);
+        assert_eq!(
+            groups[0]
+                .members
+                .iter()
+                .map(|m| m.reddit_id_int)
+                .collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+
+        assert_eq!(groups[1].count, 1);
+        assert_eq!(groups[1].earliest.reddit_id_int, 9);
+        assert!(groups[1].members.is_empty());
+    }
+
+    #[test]
+    fn to_query_echo() {
+        assert_eq!(Form::default().to_query(), "");
+
+        let form = Form {
+            link: "https://i.redd.it/a b.jpg".to_string(),
+            distance: "4".to_string(),
+            subreddits: "pics -funny".to_string(),
+            cursor: "0_1_1500000000_42".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            form.to_query(),
+            "imagelink=https%3A%2F%2Fi.redd.it%2Fa+b.jpg&distance=4\
+             &subreddits=pics+-funny&cursor=0_1_1500000000_42"
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use common::*;
+use std::error::Error as _;
+use tokio_postgres::error::{DbError, SqlState};
+use tokio_postgres::types::ToSql;
+
+/// Collects the conditions of a query along with their arguments,
+/// numbering each argument's placeholder as it's added
+#[derive(Default)]
+pub struct QueryBuilder {
+    conditions: Vec<String>,
+    args: Vec<Box<dyn ToSql + Sync + Send>>,
+}
+
+impl QueryBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an argument and returns the placeholder to refer to it with
+    pub fn arg<T: ToSql + Sync + Send + 'static>(&mut self, value: T) -> String {
+        self.args.push(Box::new(value));
+        format!("${}", self.args.len())
+    }
+
+    pub fn and<S: Into<String>>(&mut self, condition: S) -> &mut Self {
+        self.conditions.push(condition.into());
+        self
+    }
+
+    /// All added conditions, each preceded by `AND`
+    pub fn conditions(&self) -> String {
+        self.conditions
+            .iter()
+            .map(|condition| format!(" AND {}", condition))
+            .collect()
+    }
+
+    pub fn args(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.args
+            .iter()
+            .map(|arg| &**arg as &(dyn ToSql + Sync))
+            .collect()
+    }
+}
+
+/// Tells the user when a query ran past its statement timeout
+pub fn timed_out(e: tokio_postgres::Error) -> UserError {
+    match e.source().and_then(|e| e.downcast_ref::<DbError>()) {
+        Some(dberror)
+            if *dberror.code() == SqlState::QUERY_CANCELED
+                && dberror.message() == "canceling statement due to statement timeout" =>
+        {
+            ue!("query took too long", Source::User)
+        }
+        _ => e.into(),
+    }
+}
@@ -0,0 +1,267 @@
+use super::limit::{self, Client};
+use common::*;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use tera::Context;
+use warp::http::{header, Response};
+use warp::Reply;
+
+#[derive(Deserialize)]
+pub struct WatchForm {
+    hash: String,
+    distance: Option<String>,
+    subreddits: Option<String>,
+    webhook: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Watch {
+    id: i64,
+    token: String,
+    secret: String,
+    feed: String,
+}
+
+#[derive(Serialize)]
+struct ApiWatch {
+    watch: Option<Watch>,
+    error: Option<UserError>,
+}
+
+#[derive(Serialize)]
+struct FeedHit {
+    id: i64,
+    image_id: i64,
+    link: String,
+    distance: i64,
+    created: String,
+    title: Option<String>,
+    permalink: Option<String>,
+    author: Option<String>,
+    subreddit: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Feed {
+    id: i64,
+    token: String,
+    hash: String,
+    distance: i64,
+    subreddits: Option<Vec<String>>,
+    updated: String,
+    hits: Vec<FeedHit>,
+}
+
+async fn create(form: WatchForm, key_id: i64) -> Result<Watch, UserError> {
+    let hash: Hash = form
+        .hash
+        .trim()
+        .parse()
+        .map_err(map_ue!("invalid hash parameter", Source::User))?;
+
+    let distance: u8 = match form.distance.as_deref().map(str::trim) {
+        None | Some("") => 0,
+        Some(distance) => distance
+            .parse()
+            .map_err(map_ue!("invalid distance parameter", Source::User))?,
+    };
+    if distance > CONFIG.max_distance {
+        return Err(ue!(
+            format!(
+                "distance is too large, the limit is {}",
+                CONFIG.max_distance
+            ),
+            Source::User
+        ));
+    }
+
+    // Stored lowercased so they can be compared against any casing of a post's subreddit
+    let subreddits = form
+        .subreddits
+        .map(|subreddits| {
+            subreddits
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .map(|subreddit| subreddit.trim_start_matches("r/").to_lowercase())
+                .filter(|subreddit| !subreddit.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .filter(|subreddits| !subreddits.is_empty());
+
+    let webhook = match form.webhook.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(webhook) => {
+            let url = url::Url::parse(webhook)
+                .map_err(map_ue!("invalid webhook parameter", Source::User))?;
+            if url.scheme() != "http" && url.scheme() != "https" {
+                return Err(ue!("webhook must be an http or https URL", Source::User));
+            }
+            if !resolves_publicly(&url).await {
+                return Err(ue!(
+                    "webhook must be on a host with public addresses",
+                    Source::User
+                ));
+            }
+            Some(url.to_string())
+        }
+    };
+
+    let token = watch::random_token();
+    let secret = watch::random_token();
+
+    let id: i64 = PG_POOL
+        .get()
+        .await?
+        .query_one(
+            "INSERT INTO watches (hash, distance, subreddits, webhook, secret, token, key_id, created) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
+            &[
+                &hash,
+                &(distance as i64),
+                &subreddits,
+                &webhook,
+                &secret,
+                &token,
+                &key_id,
+                &chrono::offset::Utc::now().naive_utc(),
+            ],
+        )
+        .await?
+        .get("id");
+
+    info!("created watch {} for {}", id, hash);
+
+    Ok(Watch {
+        id,
+        feed: format!("/watch/{}/feed", token),
+        token,
+        secret,
+    })
+}
+
+/// Creating or deleting a watch takes a token from its API key's bucket.
+/// Only the key that created a watch can delete it, since its token is public in the feed URL.
+pub async fn post_response(form: WatchForm, client: Client) -> warp::reply::Response {
+    let key_id = match limit::take_keyed(&client, 1).await {
+        Ok(key_id) => key_id,
+        Err(refused) => return refused.json_response(),
+    };
+
+    let (watch, error, status) = match create(form, key_id).await {
+        Ok(watch) => (Some(watch), None, StatusCode::CREATED),
+        Err(error) => {
+            warn!("{}", error.error);
+            let status = error.status_code();
+            (None, Some(error), status)
+        }
+    };
+
+    warp::reply::with_status(warp::reply::json(&ApiWatch { watch, error }), status).into_response()
+}
+
+pub async fn delete_response(
+    token: String,
+    client: Client,
+) -> Result<Option<warp::reply::Response>, UserError> {
+    let key_id = match limit::take_keyed(&client, 1).await {
+        Ok(key_id) => key_id,
+        Err(refused) => return Ok(Some(refused.json_response())),
+    };
+
+    let deactivated = PG_POOL
+        .get()
+        .await?
+        .execute(
+            "UPDATE watches SET active = FALSE WHERE token = $1 AND key_id = $2 AND active",
+            &[&token, &key_id],
+        )
+        .await?;
+
+    Ok(if deactivated == 0 {
+        None
+    } else {
+        Some(StatusCode::NO_CONTENT.into_response())
+    })
+}
+
+async fn get_feed(token: String) -> Result<Option<Feed>, UserError> {
+    let client = PG_POOL.get().await?;
+
+    let watch = match client
+        .query_opt(
+            "SELECT id, hash, distance, subreddits, created FROM watches WHERE token = $1",
+            &[&token],
+        )
+        .await?
+    {
+        Some(watch) => watch,
+        None => return Ok(None),
+    };
+
+    let id: i64 = watch.get("id");
+
+    let hits = client
+        .query(
+            "SELECT watch_hits.id, watch_hits.image_id, images.link, watch_hits.distance, \
+             watch_hits.created, posts.title, posts.permalink, posts.author, posts.subreddit \
+             FROM watch_hits \
+             INNER JOIN images ON images.id = watch_hits.image_id \
+             LEFT JOIN posts ON posts.reddit_id_int = watch_hits.reddit_id_int \
+             WHERE watch_hits.watch_id = $1 \
+             AND NOT EXISTS (SELECT FROM takedowns WHERE takedowns.image_id = images.id) \
+             AND NOT EXISTS (SELECT FROM takedowns WHERE takedowns.post_id = posts.id) \
+             ORDER BY watch_hits.created DESC, watch_hits.id DESC LIMIT $2",
+            &[&id, &CONFIG.max_results],
+        )
+        .await?
+        .iter()
+        .map(|row| FeedHit {
+            id: row.get("id"),
+            image_id: row.get("image_id"),
+            link: row.get("link"),
+            distance: row.get("distance"),
+            created: rfc3339(row.get("created")),
+            title: row.get("title"),
+            permalink: row
+                .get::<_, Option<&str>>("permalink")
+                .map(|permalink| format!("https://reddit.com{}", permalink)),
+            author: row.get("author"),
+            subreddit: row.get("subreddit"),
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Some(Feed {
+        id,
+        token,
+        hash: Hash(watch.get::<_, i64>("hash") as u64).to_string(),
+        distance: watch.get("distance"),
+        subreddits: watch.get("subreddits"),
+        updated: hits
+            .first()
+            .map(|hit| hit.created.clone())
+            .unwrap_or_else(|| rfc3339(watch.get("created"))),
+        hits,
+    }))
+}
+
+fn rfc3339(at: chrono::NaiveDateTime) -> String {
+    at.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+pub async fn feed_response(token: String) -> Result<Option<impl warp::Reply>, UserError> {
+    let feed = match get_feed(token).await? {
+        Some(feed) => feed,
+        None => return Ok(None),
+    };
+
+    let tera = super::get_tera!();
+
+    let out = tera.render("watch_feed.xml", &Context::from_serialize(&feed)?)?;
+
+    Ok(Some(
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/atom+xml")
+            .body(out)
+            .unwrap(),
+    ))
+}
@@ -14,15 +14,24 @@ const BASE_STREAM_URL: &str = "http://stream.pushshift.io?type=submissions&is_se
 const NEWLINE_CODE: u8 = 10;
 
 async fn ingest_post(post: Submission) -> bool {
-    let post_url_res = post.choose_url();
+    let post_url_res = (|| async {
+        let post_url = post.choose_url()?;
+
+        if let Some(banned) = ban_list().await?.find(post_url.as_str()) {
+            return Err(ue_save!("banned", banned.save_error()));
+        }
+
+        Ok(post_url)
+    })()
+    .await;
 
     let save_res = match post_url_res {
-        Ok(post_url) => save_hash(post_url.as_str(), HashDest::Images).await,
+        Ok(post_url) => save_hash(post_url.as_str(), HashDest::Images, FetchPolicy::Trusted).await,
         Err(e) => Err(e),
     };
 
-    let image_id = match save_res {
-        Ok(hash_gotten) => Ok(hash_gotten.id),
+    let saved = match save_res {
+        Ok(hash_gotten) => Ok(hash_gotten),
         Err(ue) => match ue.source {
             Source::Internal => {
                 eprintln!(
@@ -77,15 +86,20 @@ async fn ingest_post(post: Submission) -> bool {
         },
     };
 
-    let good = image_id.is_ok();
-
-    match post.save(image_id).await {
+    match post
+        .save(saved.as_ref().map(|saved| saved.id).map_err(Clone::clone))
+        .await
+    {
         Ok(already_have) => {
-            if good {
+            if let Ok(saved) = &saved {
                 if already_have {
                     info!("already have");
                 } else {
                     info!("successfully saved");
+
+                    if let Err(e) = watch::check_watches(saved, &post).await {
+                        warn!("failed to check watches: {}", e.error);
+                    }
                 }
             }
             already_have
@@ -226,6 +240,8 @@ async fn stream(mut last_id: Option<i64>) -> Result<(), (Option<i64>, UserError)
 async fn main() -> Result<(), UserError> {
     tracing_subscriber::fmt::init();
 
+    metrics::listen("stream");
+
     let mut get_id = !std::env::args().skip(1).any(|a| a == "-i");
 
     let client = PG_POOL.get().await?;
@@ -30,6 +30,149 @@ CREATE EXTENSION IF NOT EXISTS bktree WITH SCHEMA public;
 COMMENT ON EXTENSION bktree IS 'BK-tree implementation';
 
 
+--
+-- Name: api_key_usage; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.api_key_usage (
+    key_id bigint NOT NULL,
+    day date NOT NULL,
+    searches bigint DEFAULT 0 NOT NULL
+);
+
+
+--
+-- Name: api_keys; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.api_keys (
+    id bigint NOT NULL,
+    name character varying NOT NULL,
+    digest character varying NOT NULL,
+    capacity bigint,
+    per_minute bigint,
+    daily_quota bigint,
+    allowed_origins character varying[],
+    created timestamp without time zone NOT NULL,
+    revoked timestamp without time zone
+);
+
+
+--
+-- Name: api_keys_id_seq; Type: SEQUENCE; Schema: public; Owner: -
+--
+
+CREATE SEQUENCE public.api_keys_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: api_keys_id_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: -
+--
+
+ALTER SEQUENCE public.api_keys_id_seq OWNED BY public.api_keys.id;
+
+
+--
+-- Name: audit_log; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.audit_log (
+    id bigint NOT NULL,
+    admin character varying NOT NULL,
+    action character varying NOT NULL,
+    details character varying NOT NULL,
+    created timestamp without time zone NOT NULL
+);
+
+
+--
+-- Name: audit_log_id_seq; Type: SEQUENCE; Schema: public; Owner: -
+--
+
+CREATE SEQUENCE public.audit_log_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: audit_log_id_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: -
+--
+
+ALTER SEQUENCE public.audit_log_id_seq OWNED BY public.audit_log.id;
+
+
+--
+-- Name: banned_rules; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.banned_rules (
+    id bigint NOT NULL,
+    rule character varying NOT NULL,
+    created timestamp without time zone NOT NULL,
+    created_by character varying NOT NULL
+);
+
+
+--
+-- Name: banned_rules_id_seq; Type: SEQUENCE; Schema: public; Owner: -
+--
+
+CREATE SEQUENCE public.banned_rules_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: banned_rules_id_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: -
+--
+
+ALTER SEQUENCE public.banned_rules_id_seq OWNED BY public.banned_rules.id;
+
+
+--
+-- Name: denied_hashes; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.denied_hashes (
+    id bigint NOT NULL,
+    hash bigint NOT NULL,
+    distance bigint DEFAULT 0 NOT NULL,
+    reason character varying,
+    created timestamp without time zone NOT NULL,
+    created_by character varying
+);
+
+
+--
+-- Name: denied_hashes_id_seq; Type: SEQUENCE; Schema: public; Owner: -
+--
+
+CREATE SEQUENCE public.denied_hashes_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: denied_hashes_id_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: -
+--
+
+ALTER SEQUENCE public.denied_hashes_id_seq OWNED BY public.denied_hashes.id;
+
+
 --
 -- Name: image_cache_id_seq; Type: SEQUENCE; Schema: public; Owner: -
 --
@@ -54,6 +197,11 @@ CREATE TABLE public.image_cache (
     id bigint DEFAULT nextval('public.image_cache_id_seq'::regclass) NOT NULL,
     link character varying NOT NULL,
     hash bigint NOT NULL,
+    ahash bigint,
+    phash bigint,
+    whash bigint,
+    wide_hash bit(256),
+    trimmed_hash bigint,
     no_store boolean,
     no_cache boolean,
     expires timestamp without time zone,
@@ -63,6 +211,50 @@ CREATE TABLE public.image_cache (
 );
 
 
+--
+-- Name: image_cache_frames; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.image_cache_frames (
+    image_id bigint NOT NULL,
+    frame integer NOT NULL,
+    hash bigint NOT NULL
+);
+
+
+--
+-- Name: image_counts; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.image_counts (
+    day date NOT NULL,
+    subreddit character varying NOT NULL,
+    hash bigint NOT NULL,
+    num bigint NOT NULL
+);
+
+
+--
+-- Name: image_frames; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.image_frames (
+    image_id bigint NOT NULL,
+    frame integer NOT NULL,
+    hash bigint NOT NULL
+);
+
+
+--
+-- Name: image_totals; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.image_totals (
+    hash bigint NOT NULL,
+    num bigint NOT NULL
+);
+
+
 --
 -- Name: images; Type: TABLE; Schema: public; Owner: -
 --
@@ -71,6 +263,11 @@ CREATE TABLE public.images (
     id bigint NOT NULL,
     link character varying NOT NULL,
     hash bigint NOT NULL,
+    ahash bigint,
+    phash bigint,
+    whash bigint,
+    wide_hash bit(256),
+    trimmed_hash bigint,
     no_store boolean,
     no_cache boolean,
     expires timestamp without time zone,
@@ -149,6 +346,140 @@ CREATE SEQUENCE public.posts_id_seq
 ALTER SEQUENCE public.posts_id_seq OWNED BY public.posts.id;
 
 
+--
+-- Name: takedowns; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.takedowns (
+    id bigint NOT NULL,
+    post_id bigint,
+    image_id bigint,
+    reason character varying,
+    created timestamp without time zone NOT NULL,
+    created_by character varying NOT NULL,
+    CONSTRAINT takedowns_check CHECK (((post_id IS NULL) <> (image_id IS NULL)))
+);
+
+
+--
+-- Name: takedowns_id_seq; Type: SEQUENCE; Schema: public; Owner: -
+--
+
+CREATE SEQUENCE public.takedowns_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: takedowns_id_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: -
+--
+
+ALTER SEQUENCE public.takedowns_id_seq OWNED BY public.takedowns.id;
+
+
+--
+-- Name: watch_hits; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.watch_hits (
+    id bigint NOT NULL,
+    watch_id bigint NOT NULL,
+    image_id bigint NOT NULL,
+    reddit_id_int bigint NOT NULL,
+    distance bigint NOT NULL,
+    created timestamp without time zone NOT NULL,
+    delivered_on timestamp without time zone,
+    delivery_error character varying
+);
+
+
+--
+-- Name: watch_hits_id_seq; Type: SEQUENCE; Schema: public; Owner: -
+--
+
+CREATE SEQUENCE public.watch_hits_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: watch_hits_id_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: -
+--
+
+ALTER SEQUENCE public.watch_hits_id_seq OWNED BY public.watch_hits.id;
+
+
+--
+-- Name: watches; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.watches (
+    id bigint NOT NULL,
+    hash bigint NOT NULL,
+    distance bigint NOT NULL,
+    subreddits character varying[],
+    webhook character varying,
+    secret character varying NOT NULL,
+    token character varying NOT NULL,
+    key_id bigint NOT NULL,
+    created timestamp without time zone NOT NULL,
+    active boolean DEFAULT true NOT NULL
+);
+
+
+--
+-- Name: watches_id_seq; Type: SEQUENCE; Schema: public; Owner: -
+--
+
+CREATE SEQUENCE public.watches_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: watches_id_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: -
+--
+
+ALTER SEQUENCE public.watches_id_seq OWNED BY public.watches.id;
+
+
+--
+-- Name: api_keys id; Type: DEFAULT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.api_keys ALTER COLUMN id SET DEFAULT nextval('public.api_keys_id_seq'::regclass);
+
+
+--
+-- Name: audit_log id; Type: DEFAULT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.audit_log ALTER COLUMN id SET DEFAULT nextval('public.audit_log_id_seq'::regclass);
+
+
+--
+-- Name: banned_rules id; Type: DEFAULT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.banned_rules ALTER COLUMN id SET DEFAULT nextval('public.banned_rules_id_seq'::regclass);
+
+
+--
+-- Name: denied_hashes id; Type: DEFAULT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.denied_hashes ALTER COLUMN id SET DEFAULT nextval('public.denied_hashes_id_seq'::regclass);
+
+
 --
 -- Name: images id; Type: DEFAULT; Schema: public; Owner: -
 --
@@ -163,6 +494,75 @@ ALTER TABLE ONLY public.images ALTER COLUMN id SET DEFAULT nextval('public.image
 ALTER TABLE ONLY public.posts ALTER COLUMN id SET DEFAULT nextval('public.posts_id_seq'::regclass);
 
 
+--
+-- Name: takedowns id; Type: DEFAULT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.takedowns ALTER COLUMN id SET DEFAULT nextval('public.takedowns_id_seq'::regclass);
+
+
+--
+-- Name: watch_hits id; Type: DEFAULT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.watch_hits ALTER COLUMN id SET DEFAULT nextval('public.watch_hits_id_seq'::regclass);
+
+
+--
+-- Name: watches id; Type: DEFAULT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.watches ALTER COLUMN id SET DEFAULT nextval('public.watches_id_seq'::regclass);
+
+
+--
+-- Name: api_key_usage api_key_usage_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.api_key_usage
+    ADD CONSTRAINT api_key_usage_pkey PRIMARY KEY (key_id, day);
+
+
+--
+-- Name: api_keys api_keys_digest_key; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.api_keys
+    ADD CONSTRAINT api_keys_digest_key UNIQUE (digest);
+
+
+--
+-- Name: api_keys api_keys_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.api_keys
+    ADD CONSTRAINT api_keys_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: audit_log audit_log_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.audit_log
+    ADD CONSTRAINT audit_log_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: banned_rules banned_rules_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.banned_rules
+    ADD CONSTRAINT banned_rules_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: denied_hashes denied_hashes_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.denied_hashes
+    ADD CONSTRAINT denied_hashes_pkey PRIMARY KEY (id);
+
+
 --
 -- Name: image_cache image_cache_link_key; Type: CONSTRAINT; Schema: public; Owner: -
 --
@@ -179,6 +579,38 @@ ALTER TABLE ONLY public.image_cache
     ADD CONSTRAINT image_cache_pkey PRIMARY KEY (id);
 
 
+--
+-- Name: image_cache_frames image_cache_frames_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.image_cache_frames
+    ADD CONSTRAINT image_cache_frames_pkey PRIMARY KEY (image_id, frame);
+
+
+--
+-- Name: image_counts image_counts_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.image_counts
+    ADD CONSTRAINT image_counts_pkey PRIMARY KEY (day, subreddit, hash);
+
+
+--
+-- Name: image_frames image_frames_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.image_frames
+    ADD CONSTRAINT image_frames_pkey PRIMARY KEY (image_id, frame);
+
+
+--
+-- Name: image_totals image_totals_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.image_totals
+    ADD CONSTRAINT image_totals_pkey PRIMARY KEY (hash);
+
+
 --
 -- Name: images images_link_key; Type: CONSTRAINT; Schema: public; Owner: -
 --
@@ -227,6 +659,38 @@ ALTER TABLE ONLY public.posts
     ADD CONSTRAINT posts_reddit_id_key UNIQUE (reddit_id);
 
 
+--
+-- Name: takedowns takedowns_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.takedowns
+    ADD CONSTRAINT takedowns_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: watch_hits watch_hits_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.watch_hits
+    ADD CONSTRAINT watch_hits_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: watches watches_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.watches
+    ADD CONSTRAINT watches_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: watches watches_token_key; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.watches
+    ADD CONSTRAINT watches_token_key UNIQUE (token);
+
+
 --
 -- Name: image_cache_hash_idx; Type: INDEX; Schema: public; Owner: -
 --
@@ -234,6 +698,34 @@ ALTER TABLE ONLY public.posts
 CREATE INDEX image_cache_hash_idx ON public.image_cache USING spgist (hash public.bktree_ops);
 
 
+--
+-- Name: image_counts_day_hash_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX image_counts_day_hash_idx ON public.image_counts USING btree (day, hash);
+
+
+--
+-- Name: image_counts_subreddit_day_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX image_counts_subreddit_day_idx ON public.image_counts USING btree (subreddit, day);
+
+
+--
+-- Name: image_frames_hash_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX image_frames_hash_idx ON public.image_frames USING spgist (hash public.bktree_ops);
+
+
+--
+-- Name: image_totals_num_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX image_totals_num_idx ON public.image_totals USING btree (num);
+
+
 --
 -- Name: images_hash_idx; Type: INDEX; Schema: public; Owner: -
 --
@@ -241,6 +733,13 @@ CREATE INDEX image_cache_hash_idx ON public.image_cache USING spgist (hash publi
 CREATE INDEX images_hash_idx ON public.images USING spgist (hash public.bktree_ops);
 
 
+--
+-- Name: images_trimmed_hash_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX images_trimmed_hash_idx ON public.images USING spgist (trimmed_hash public.bktree_ops);
+
+
 --
 -- Name: posts_author_idx; Type: INDEX; Schema: public; Owner: -
 --
@@ -255,6 +754,20 @@ CREATE INDEX posts_author_idx ON public.posts USING btree (author);
 CREATE INDEX posts_image_id_idx ON public.posts USING btree (image_id);
 
 
+--
+-- Name: posts_lower_author_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX posts_lower_author_idx ON public.posts USING btree (lower((author)::text));
+
+
+--
+-- Name: posts_lower_subreddit_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX posts_lower_subreddit_idx ON public.posts USING btree (lower((subreddit)::text));
+
+
 --
 -- Name: posts_subreddit_idx; Type: INDEX; Schema: public; Owner: -
 --
@@ -262,6 +775,58 @@ CREATE INDEX posts_image_id_idx ON public.posts USING btree (image_id);
 CREATE INDEX posts_subreddit_idx ON public.posts USING btree (subreddit);
 
 
+--
+-- Name: takedowns_image_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX takedowns_image_id_idx ON public.takedowns USING btree (image_id);
+
+
+--
+-- Name: takedowns_post_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX takedowns_post_id_idx ON public.takedowns USING btree (post_id);
+
+
+--
+-- Name: watch_hits_watch_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX watch_hits_watch_id_idx ON public.watch_hits USING btree (watch_id);
+
+
+--
+-- Name: watches_hash_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX watches_hash_idx ON public.watches USING spgist (hash public.bktree_ops);
+
+
+--
+-- Name: api_key_usage api_key_usage_key_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.api_key_usage
+    ADD CONSTRAINT api_key_usage_key_id_fkey FOREIGN KEY (key_id) REFERENCES public.api_keys(id);
+
+
+--
+-- Name: image_cache_frames image_cache_frames_image_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.image_cache_frames
+    ADD CONSTRAINT image_cache_frames_image_id_fkey FOREIGN KEY (image_id) REFERENCES public.image_cache(id) ON DELETE CASCADE;
+
+
+--
+-- Name: image_frames image_frames_image_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.image_frames
+    ADD CONSTRAINT image_frames_image_id_fkey FOREIGN KEY (image_id) REFERENCES public.images(id);
+
+
 --
 -- Name: posts posts_image_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
 --
@@ -270,6 +835,102 @@ ALTER TABLE ONLY public.posts
     ADD CONSTRAINT posts_image_id_fkey FOREIGN KEY (image_id) REFERENCES public.images(id);
 
 
+--
+-- Name: takedowns takedowns_image_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.takedowns
+    ADD CONSTRAINT takedowns_image_id_fkey FOREIGN KEY (image_id) REFERENCES public.images(id);
+
+
+--
+-- Name: takedowns takedowns_post_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.takedowns
+    ADD CONSTRAINT takedowns_post_id_fkey FOREIGN KEY (post_id) REFERENCES public.posts(id);
+
+
+--
+-- Name: watch_hits watch_hits_image_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.watch_hits
+    ADD CONSTRAINT watch_hits_image_id_fkey FOREIGN KEY (image_id) REFERENCES public.images(id);
+
+
+--
+-- Name: watch_hits watch_hits_watch_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.watch_hits
+    ADD CONSTRAINT watch_hits_watch_id_fkey FOREIGN KEY (watch_id) REFERENCES public.watches(id);
+
+
+--
+-- Name: watches watches_key_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.watches
+    ADD CONSTRAINT watches_key_id_fkey FOREIGN KEY (key_id) REFERENCES public.api_keys(id);
+
+
+--
+-- Name: TABLE api_key_usage; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT SELECT,INSERT,UPDATE ON TABLE public.api_key_usage TO site;
+
+
+--
+-- Name: TABLE api_keys; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT SELECT ON TABLE public.api_keys TO site;
+
+
+--
+-- Name: TABLE audit_log; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT SELECT,INSERT ON TABLE public.audit_log TO site;
+
+
+--
+-- Name: SEQUENCE audit_log_id_seq; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT ALL ON SEQUENCE public.audit_log_id_seq TO site;
+
+
+--
+-- Name: TABLE banned_rules; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT SELECT,INSERT,DELETE ON TABLE public.banned_rules TO site;
+
+
+--
+-- Name: SEQUENCE banned_rules_id_seq; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT ALL ON SEQUENCE public.banned_rules_id_seq TO site;
+
+
+--
+-- Name: TABLE denied_hashes; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT SELECT,INSERT,DELETE ON TABLE public.denied_hashes TO site;
+
+
+--
+-- Name: SEQUENCE denied_hashes_id_seq; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT ALL ON SEQUENCE public.denied_hashes_id_seq TO site;
+
+
 --
 -- Name: SEQUENCE image_cache_id_seq; Type: ACL; Schema: public; Owner: -
 --
@@ -284,6 +945,34 @@ GRANT ALL ON SEQUENCE public.image_cache_id_seq TO site;
 GRANT SELECT,INSERT,DELETE,UPDATE ON TABLE public.image_cache TO site;
 
 
+--
+-- Name: TABLE image_cache_frames; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT SELECT,INSERT,DELETE ON TABLE public.image_cache_frames TO site;
+
+
+--
+-- Name: TABLE image_counts; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT SELECT,INSERT,DELETE,UPDATE ON TABLE public.image_counts TO site;
+
+
+--
+-- Name: TABLE image_frames; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT SELECT,INSERT,DELETE ON TABLE public.image_frames TO site;
+
+
+--
+-- Name: TABLE image_totals; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT SELECT,INSERT,DELETE,UPDATE ON TABLE public.image_totals TO site;
+
+
 --
 -- Name: TABLE images; Type: ACL; Schema: public; Owner: -
 --
@@ -312,6 +1001,48 @@ GRANT SELECT,INSERT,DELETE,UPDATE ON TABLE public.posts TO site;
 GRANT ALL ON SEQUENCE public.posts_id_seq TO site;
 
 
+--
+-- Name: TABLE takedowns; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT SELECT,INSERT,DELETE ON TABLE public.takedowns TO site;
+
+
+--
+-- Name: SEQUENCE takedowns_id_seq; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT ALL ON SEQUENCE public.takedowns_id_seq TO site;
+
+
+--
+-- Name: TABLE watch_hits; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT SELECT,INSERT,DELETE,UPDATE ON TABLE public.watch_hits TO site;
+
+
+--
+-- Name: SEQUENCE watch_hits_id_seq; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT ALL ON SEQUENCE public.watch_hits_id_seq TO site;
+
+
+--
+-- Name: TABLE watches; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT SELECT,INSERT,DELETE,UPDATE ON TABLE public.watches TO site;
+
+
+--
+-- Name: SEQUENCE watches_id_seq; Type: ACL; Schema: public; Owner: -
+--
+
+GRANT ALL ON SEQUENCE public.watches_id_seq TO site;
+
+
 --
 -- PostgreSQL database dump complete
 --